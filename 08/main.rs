@@ -1,4 +1,10 @@
-use std::{collections::HashSet, error::Error, fs::read_to_string};
+use std::{
+    collections::{HashSet, VecDeque},
+    env::args,
+    error::Error,
+    fmt::{self, Display, Formatter},
+    fs::{read_to_string, write},
+};
 
 struct Instruction {
     name: String,
@@ -25,6 +31,20 @@ impl Instruction {
         }
     }
 
+    /// Returns the instruction pointer after executing this instruction at `index`.
+    /// Any target outside of the program counts as termination and maps to `len`.
+    fn successor(&self, index: usize, len: usize) -> usize {
+        let target = match self.name.as_str() {
+            "jmp" => index as isize + self.count,
+            _ => index as isize + 1,
+        };
+        if (0..len as isize).contains(&target) {
+            target as usize
+        } else {
+            len
+        }
+    }
+
     fn toggle(&self) -> Option<Self> {
         match self.name.as_str() {
             "jmp" => Some(Self {
@@ -40,8 +60,153 @@ impl Instruction {
     }
 }
 
+impl Display for Instruction {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        write!(f, "{} {:+}", self.name, self.count)
+    }
+}
+
+struct TraceStep {
+    step: usize,
+    instruction_pointer: usize,
+    accumulator_before: isize,
+    accumulator_after: isize,
+}
+
+/// Writes the trace as tab separated lines, one per executed instruction, followed by
+/// how the run ended: `terminated` when the instruction pointer left the program,
+/// `loop` when it came back to an already executed instruction.
+fn format_trace(
+    program: &[Instruction],
+    trace: &[TraceStep],
+    end_instruction_pointer: usize,
+) -> String {
+    let mut output = "step\tip\tinstruction\tacc_before\tacc_after\n".to_string();
+    for step in trace {
+        output += &format!(
+            "{}\t{}\t{}\t{}\t{}\n",
+            step.step,
+            step.instruction_pointer,
+            program[step.instruction_pointer],
+            step.accumulator_before,
+            step.accumulator_after
+        );
+    }
+    let end = if end_instruction_pointer < program.len() {
+        "loop"
+    } else {
+        "terminated"
+    };
+    output + &format!("{end}\t{}\n", end_instruction_pointer as isize)
+}
+
+/// Lists how often every instruction was executed, followed by all never reached lines.
+fn format_coverage(program: &[Instruction], trace: &[TraceStep]) -> String {
+    let mut execution_counts = vec![0; program.len()];
+    for step in trace {
+        execution_counts[step.instruction_pointer] += 1;
+    }
+
+    let executed_count = execution_counts.iter().filter(|&&count| count > 0).count();
+    let mut output = format!(
+        "coverage: {executed_count}/{} instructions executed\n",
+        program.len()
+    );
+    for (index, instruction) in program.iter().enumerate() {
+        output += &format!(
+            "{index:>5} {:>6}x  {instruction}\n",
+            execution_counts[index]
+        );
+    }
+    output += "never reached:";
+    for (index, _) in execution_counts
+        .iter()
+        .enumerate()
+        .filter(|(_, &count)| count == 0)
+    {
+        output += &format!(" {index}");
+    }
+    output + "\n"
+}
+
+struct Repair {
+    index: usize,
+    original: String,
+    accumulator: isize,
+}
+
+/// Finds every single `jmp`/`nop` flip that makes the program terminate in linear time.
+///
+/// First walks the control flow graph backwards from the exit to find all instructions
+/// that terminate unchanged (together with the accumulator they add up until the end),
+/// then follows the original program once and checks whether flipping the current
+/// instruction lands on one of those.
+fn find_repairs(program: &[Instruction]) -> Vec<Repair> {
+    let len = program.len();
+    let mut predecessors = vec![Vec::new(); len + 1];
+    for (index, instruction) in program.iter().enumerate() {
+        predecessors[instruction.successor(index, len)].push(index);
+    }
+
+    let mut accumulator_to_exit = vec![None; len + 1];
+    accumulator_to_exit[len] = Some(0);
+    let mut queue = VecDeque::from([len]);
+    while let Some(index) = queue.pop_front() {
+        let accumulator = accumulator_to_exit[index].unwrap_or_default();
+        for &predecessor in &predecessors[index] {
+            let instruction = &program[predecessor];
+            let added = if instruction.name == "acc" {
+                instruction.count
+            } else {
+                0
+            };
+            accumulator_to_exit[predecessor] = Some(accumulator + added);
+            queue.push_back(predecessor);
+        }
+    }
+
+    let (mut instruction_pointer, mut accumulator, mut visited_instructions) =
+        (0, 0, HashSet::new());
+    let mut repairs = Vec::new();
+
+    while instruction_pointer < len
+        && accumulator_to_exit[instruction_pointer].is_none()
+        && visited_instructions.insert(instruction_pointer)
+    {
+        let instruction = &program[instruction_pointer];
+        if let Some(toggled_instruction) = instruction.toggle() {
+            let target = toggled_instruction.successor(instruction_pointer, len);
+            if let Some(remaining) = accumulator_to_exit[target] {
+                repairs.push(Repair {
+                    index: instruction_pointer,
+                    original: instruction.name.clone(),
+                    accumulator: accumulator + remaining,
+                });
+            }
+        }
+        instruction.execute(&mut instruction_pointer, &mut accumulator);
+    }
+
+    repairs
+}
+
 fn main() -> Result<(), Box<dyn Error>> {
-    let mut program = read_to_string("input.txt")?
+    let (mut trace_path, mut coverage_path) = (None, None);
+    let mut arguments = args().skip(1);
+    while let Some(option) = arguments.next() {
+        let mut value = || {
+            arguments
+                .next()
+                .ok_or(format!("missing value for {option}"))
+        };
+        match option.as_str() {
+            "--trace" => trace_path = Some(value()?),
+            "--coverage" => coverage_path = Some(value()?),
+            _ => return Err(format!("unknown option {option}").into()),
+        }
+    }
+
+    let program = read_to_string("input.txt")?
         .split("\n")
         .map(Instruction::parse)
         .collect::<Vec<_>>();
@@ -49,31 +214,41 @@ fn main() -> Result<(), Box<dyn Error>> {
     let (mut instruction_pointer, mut accumulator, mut visited_instructions) =
         (0, 0, HashSet::new());
 
-    while visited_instructions.insert(instruction_pointer) {
+    let mut trace = Vec::new();
+
+    while instruction_pointer < program.len() && visited_instructions.insert(instruction_pointer) {
+        let (step, accumulator_before) = (trace.len(), accumulator);
+        let traced_instruction_pointer = instruction_pointer;
         program[instruction_pointer].execute(&mut instruction_pointer, &mut accumulator);
+        trace.push(TraceStep {
+            step,
+            instruction_pointer: traced_instruction_pointer,
+            accumulator_before,
+            accumulator_after: accumulator,
+        });
+    }
+    if instruction_pointer < program.len() {
+        println!("2020-12-08 Part 1: {accumulator}");
+    } else {
+        println!("2020-12-08 Part 1: terminated with {accumulator}");
     }
-    println!("2020-12-08 Part 1: {accumulator}");
-
-    for toggle_index in 0..program.len() {
-        if let Some(toggled_instruction) = program[toggle_index].toggle() {
-            program[toggle_index] = toggled_instruction;
-
-            (instruction_pointer, accumulator) = (0, 0);
-            visited_instructions.clear();
-
-            while instruction_pointer < program.len()
-                && visited_instructions.insert(instruction_pointer)
-            {
-                program[instruction_pointer].execute(&mut instruction_pointer, &mut accumulator);
-            }
 
-            if instruction_pointer >= program.len() {
-                break;
-            }
+    if let Some(path) = trace_path {
+        write(path, format_trace(&program, &trace, instruction_pointer))?;
+    }
+    if let Some(path) = coverage_path {
+        write(path, format_coverage(&program, &trace))?;
+    }
 
-            program[toggle_index] = program[toggle_index].toggle().unwrap();
-        }
+    let repairs = find_repairs(&program);
+    for repair in &repairs {
+        println!(
+            "2020-12-08 Repair: flip {} at {} -> {}",
+            repair.original, repair.index, repair.accumulator
+        );
     }
+
+    let accumulator = repairs.first().ok_or("no repair")?.accumulator;
     println!("2020-12-08 Part 2: {accumulator}");
 
     Ok(())
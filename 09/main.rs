@@ -1,45 +1,194 @@
-use std::{collections::VecDeque, error::Error, fs::read_to_string};
+use std::{
+    collections::{HashMap, VecDeque},
+    env::args,
+    error::Error,
+    fs::read_to_string,
+    io::{stdin, BufRead, BufReader, Lines, Read},
+};
 
-fn main() -> Result<(), Box<dyn Error>> {
-    let numbers = read_to_string("input.txt")?
-        .split("\n")
-        .filter_map(|number| number.parse::<u64>().ok())
-        .collect::<Vec<_>>();
+/// Sliding window over the last `length` numbers that keeps the sums of all pairs in it
+/// as a counted multiset, so checking a number is a single lookup and sliding the window
+/// only touches the sums of the numbers entering and leaving it.
+struct PairSumWindow {
+    length: usize,
+    numbers: VecDeque<u64>,
+    pair_sums: HashMap<u64, usize>,
+}
+
+impl PairSumWindow {
+    fn new(length: usize) -> Self {
+        Self {
+            length,
+            numbers: VecDeque::with_capacity(length),
+            pair_sums: HashMap::new(),
+        }
+    }
+
+    fn is_full(&self) -> bool {
+        self.numbers.len() == self.length
+    }
 
-    let mut index = 25;
-    let mut preamble = numbers.iter().take(index).copied().collect::<VecDeque<_>>();
-    let mut invalid_number = 0;
-
-    while index < numbers.len() {
-        invalid_number = numbers[index];
-        let mut number_constructed = false;
-        'part1: for x in 0..preamble.len() - 1 {
-            for y in x + 1..preamble.len() {
-                if preamble[x] + preamble[y] == invalid_number {
-                    number_constructed = true;
-                    break 'part1;
+    fn contains_sum(&self, number: u64) -> bool {
+        self.pair_sums.contains_key(&number)
+    }
+
+    fn push(&mut self, number: u64) {
+        if self.is_full() {
+            if let Some(removed) = self.numbers.pop_front() {
+                for &other in &self.numbers {
+                    let sum = removed + other;
+                    if let Some(count) = self.pair_sums.get_mut(&sum) {
+                        *count -= 1;
+                        if *count == 0 {
+                            self.pair_sums.remove(&sum);
+                        }
+                    }
                 }
             }
         }
-        if !number_constructed {
-            println!("2020-12-09 Part 1: {invalid_number}");
-            break;
+        for &other in &self.numbers {
+            *self.pair_sums.entry(number + other).or_default() += 1;
         }
-        preamble.pop_front();
-        preamble.push_back(invalid_number);
-        index += 1;
+        self.numbers.push_back(number);
     }
+}
 
-    'part2: for x in 0..numbers.len() - 1 {
-        for y in x + 1..numbers.len() {
-            if invalid_number == numbers[x..=y].iter().sum::<u64>() {
-                let min = numbers[x..=y].iter().min().ok_or("no min")?;
-                let max = numbers[x..=y].iter().max().ok_or("no max")?;
-                println!("2020-12-09 Part 2: {}", min + max);
-                break 'part2;
+/// Returns the first number after the preamble that is not the sum of two of the
+/// `preamble_length` numbers before it.
+fn find_invalid_number(numbers: &[u64], preamble_length: usize) -> Result<u64, Box<dyn Error>> {
+    if preamble_length < 2 || numbers.len() <= preamble_length {
+        return Err("not enough numbers for preamble".into());
+    }
+
+    let mut window = PairSumWindow::new(preamble_length);
+    for &number in numbers {
+        if window.is_full() && !window.contains_sum(number) {
+            return Ok(number);
+        }
+        window.push(number);
+    }
+
+    Err("no invalid number".into())
+}
+
+struct InvalidNumber {
+    position: usize,
+    number: u64,
+    window: Vec<u64>,
+}
+
+/// Validates XMAS numbers one line at a time from any reader, yielding every number
+/// that is not the sum of two numbers in the window before it.
+///
+/// Invalid numbers still enter the window, just like valid ones, so the stream can be
+/// checked continuously without stopping at the first error. Lines that are not numbers
+/// are errors, but still count as a position, so positions always match line numbers.
+struct XmasValidator<R: Read> {
+    lines: Lines<BufReader<R>>,
+    position: usize,
+    window: PairSumWindow,
+}
+
+impl<R: Read> XmasValidator<R> {
+    fn new(reader: R, preamble_length: usize) -> Result<Self, Box<dyn Error>> {
+        if preamble_length < 2 {
+            return Err("preamble needs at least two numbers".into());
+        }
+        Ok(Self {
+            lines: BufReader::new(reader).lines(),
+            position: 0,
+            window: PairSumWindow::new(preamble_length),
+        })
+    }
+}
+
+impl<R: Read> Iterator for XmasValidator<R> {
+    type Item = Result<InvalidNumber, Box<dyn Error>>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            let line = match self.lines.next()? {
+                Ok(line) => line,
+                Err(error) => return Some(Err(error.into())),
+            };
+            let position = self.position;
+            self.position += 1;
+            let Ok(number) = line.trim().parse::<u64>() else {
+                return Some(Err(format!("invalid number {line:?} at {position}").into()));
+            };
+
+            let invalid = (self.window.is_full() && !self.window.contains_sum(number)).then(|| {
+                InvalidNumber {
+                    position,
+                    number,
+                    window: self.window.numbers.iter().copied().collect(),
+                }
+            });
+            self.window.push(number);
+
+            if let Some(invalid) = invalid {
+                return Some(Ok(invalid));
             }
         }
     }
+}
+
+/// Finds a contiguous range of at least two numbers summing up to `target`
+/// with two pointers, which works because all numbers are positive.
+fn find_weakness_range(numbers: &[u64], target: u64) -> Option<&[u64]> {
+    let (mut start, mut sum) = (0, 0);
+    for end in 0..numbers.len() {
+        sum += numbers[end];
+        while sum > target && start < end {
+            sum -= numbers[start];
+            start += 1;
+        }
+        if sum == target && start < end {
+            return Some(&numbers[start..=end]);
+        }
+    }
+    None
+}
+
+fn main() -> Result<(), Box<dyn Error>> {
+    let (mut stream, mut preamble_length) = (false, 25);
+    let mut arguments = args().skip(1);
+    while let Some(option) = arguments.next() {
+        let mut value = || {
+            arguments
+                .next()
+                .ok_or(format!("missing value for {option}"))
+        };
+        match option.as_str() {
+            "--stream" => stream = true,
+            "--preamble" => preamble_length = value()?.parse()?,
+            _ => return Err(format!("unknown option {option}").into()),
+        }
+    }
+
+    if stream {
+        for invalid in XmasValidator::new(stdin(), preamble_length)? {
+            let invalid = invalid?;
+            println!(
+                "2020-12-09 Invalid: {} at {} (window {:?})",
+                invalid.number, invalid.position, invalid.window
+            );
+        }
+        return Ok(());
+    }
+
+    let numbers = read_to_string("input.txt")?
+        .split("\n")
+        .filter_map(|number| number.parse::<u64>().ok())
+        .collect::<Vec<_>>();
+
+    let invalid_number = find_invalid_number(&numbers, preamble_length)?;
+    println!("2020-12-09 Part 1: {invalid_number}");
+
+    let range = find_weakness_range(&numbers, invalid_number).ok_or("no range")?;
+    let min = range.iter().min().ok_or("no min")?;
+    let max = range.iter().max().ok_or("no max")?;
+    println!("2020-12-09 Part 2: {}", min + max);
 
     Ok(())
 }
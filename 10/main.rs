@@ -1,9 +1,163 @@
-use std::{collections::HashMap, error::Error, fs::read_to_string, iter::once};
+use rand::{thread_rng, Rng};
+use std::{collections::HashMap, env::args, error::Error, fs::read_to_string};
+
+/// Returns the outlet (0), the sorted `adapters` and the device, which is rated
+/// `max_step` jolts above the highest adapter.
+fn joltage_chain(adapters: &[u64], max_step: u64) -> Vec<u64> {
+    let device = adapters.last().copied().unwrap_or_default() + max_step;
+    [0].into_iter()
+        .chain(adapters.iter().copied())
+        .chain([device])
+        .collect()
+}
+
+/// Counts for every joltage in the chain how many arrangements lead from it to the
+/// device, where every step is at most `max_step`.
+///
+/// Each joltage sums up the arrangements of all later joltages within reach,
+/// so this works for any gaps and run lengths.
+fn count_arrangements_to_device(
+    joltages: &[u64],
+    max_step: u64,
+) -> Result<Vec<u128>, Box<dyn Error>> {
+    let mut arrangements = vec![0u128; joltages.len()];
+    arrangements[joltages.len() - 1] = 1;
+    for index in (0..joltages.len() - 1).rev() {
+        for next in reachable_from(joltages, index, max_step) {
+            arrangements[index] = arrangements[index]
+                .checked_add(arrangements[next])
+                .ok_or("arrangement count overflow")?;
+        }
+    }
+    Ok(arrangements)
+}
+
+fn reachable_from(
+    joltages: &[u64],
+    index: usize,
+    max_step: u64,
+) -> impl Iterator<Item = usize> + '_ {
+    (index + 1..joltages.len())
+        .take_while(move |&next| joltages[next] - joltages[index] <= max_step)
+}
+
+/// Lists the first `limit` arrangements in ascending order, only descending into
+/// joltages that can still reach the device.
+fn enumerate_chains(
+    joltages: &[u64],
+    arrangements: &[u128],
+    max_step: u64,
+    limit: usize,
+) -> Vec<Vec<u64>> {
+    fn descend(
+        joltages: &[u64],
+        arrangements: &[u128],
+        max_step: u64,
+        limit: usize,
+        chain: &mut Vec<usize>,
+        chains: &mut Vec<Vec<u64>>,
+    ) {
+        let index = chain[chain.len() - 1];
+        if index == joltages.len() - 1 {
+            chains.push(chain.iter().map(|&index| joltages[index]).collect());
+            return;
+        }
+        for next in reachable_from(joltages, index, max_step) {
+            if chains.len() >= limit {
+                return;
+            }
+            if arrangements[next] > 0 {
+                chain.push(next);
+                descend(joltages, arrangements, max_step, limit, chain, chains);
+                chain.pop();
+            }
+        }
+    }
+
+    let mut chains = Vec::new();
+    if arrangements[0] > 0 && limit > 0 {
+        descend(
+            joltages,
+            arrangements,
+            max_step,
+            limit,
+            &mut vec![0],
+            &mut chains,
+        );
+    }
+    chains
+}
+
+/// Picks one arrangement uniformly at random by choosing each next joltage
+/// weighted with the number of arrangements that continue from it.
+fn sample_chain(joltages: &[u64], arrangements: &[u128], max_step: u64) -> Option<Vec<u64>> {
+    let mut rng = thread_rng();
+    let (mut index, mut chain) = (0, vec![joltages[0]]);
+    while index < joltages.len() - 1 {
+        let mut pick = rng.gen_range(0..*arrangements.get(index).filter(|&&count| count > 0)?);
+        index = reachable_from(joltages, index, max_step).find(|&next| {
+            if pick < arrangements[next] {
+                true
+            } else {
+                pick -= arrangements[next];
+                false
+            }
+        })?;
+        chain.push(joltages[index]);
+    }
+    Some(chain)
+}
+
+/// Finds the arrangement using the fewest adapters by always jumping
+/// to the highest joltage still within reach.
+fn shortest_chain(joltages: &[u64], max_step: u64) -> Option<Vec<u64>> {
+    let (mut index, mut chain) = (0, vec![joltages[0]]);
+    while index < joltages.len() - 1 {
+        index = reachable_from(joltages, index, max_step).last()?;
+        chain.push(joltages[index]);
+    }
+    Some(chain)
+}
+
+fn format_chain(chain: &[u64]) -> String {
+    chain
+        .iter()
+        .map(|joltage| joltage.to_string())
+        .collect::<Vec<_>>()
+        .join(" -> ")
+}
+
+/// A list of chains to print after the answers, in the order they were asked for.
+enum Listing {
+    Chains(usize),
+    Sample(usize),
+    Shortest,
+}
 
 fn main() -> Result<(), Box<dyn Error>> {
+    let (mut max_step, mut listings) = (3, Vec::new());
+    let mut arguments = args().skip(1);
+    while let Some(option) = arguments.next() {
+        let mut value = || {
+            arguments
+                .next()
+                .ok_or(format!("missing value for {option}"))
+        };
+        match option.as_str() {
+            "--max-step" => max_step = value()?.parse()?,
+            "--chains" => listings.push(Listing::Chains(value()?.parse()?)),
+            "--sample" => listings.push(Listing::Sample(value()?.parse()?)),
+            "--shortest" => listings.push(Listing::Shortest),
+            _ => return Err(format!("unknown option {option}").into()),
+        }
+    }
+    if max_step == 0 {
+        return Err("max step must be at least 1".into());
+    }
+
     let mut adapters = read_to_string("input.txt")?
         .split("\n")
-        .filter_map(|number| number.parse::<u8>().ok())
+        .filter_map(|number| number.parse::<u64>().ok())
         .collect::<Vec<_>>();
     adapters.sort();
 
@@ -23,26 +177,30 @@ fn main() -> Result<(), Box<dyn Error>> {
         count_map.get(&1).ok_or("no 1")? * count_map.get(&3).ok_or("no 3")?
     );
 
-    let adapter_arrangements = adapters
-        .into_iter()
-        .chain(once(u8::MAX))
-        .fold(
-            (1u64, 0, 1),
-            |(arrangement_count, prev_adapter, bundle_length), adapter| {
-                if prev_adapter + 1 == adapter {
-                    (arrangement_count, adapter, bundle_length + 1)
-                } else {
-                    match bundle_length {
-                        5 => (arrangement_count * 7, adapter, 1),
-                        4 => (arrangement_count * 4, adapter, 1),
-                        3 => (arrangement_count * 2, adapter, 1),
-                        _ => (arrangement_count, adapter, 1),
-                    }
+    let joltages = joltage_chain(&adapters, max_step);
+    let arrangements = count_arrangements_to_device(&joltages, max_step)?;
+    println!("2020-12-10 Part 2: {}", arrangements[0]);
+
+    for listing in listings {
+        match listing {
+            Listing::Chains(count) => {
+                for chain in enumerate_chains(&joltages, &arrangements, max_step, count) {
+                    println!("{}", format_chain(&chain));
+                }
+            }
+            Listing::Sample(count) => {
+                for _ in 0..count {
+                    let chain =
+                        sample_chain(&joltages, &arrangements, max_step).ok_or("no chain")?;
+                    println!("{}", format_chain(&chain));
                 }
-            },
-        )
-        .0;
+            }
+            Listing::Shortest => {
+                let chain = shortest_chain(&joltages, max_step).ok_or("no chain")?;
+                println!("{} adapters: {}", chain.len() - 2, format_chain(&chain));
+            }
+        }
+    }
 
-    println!("2020-12-10 Part 2: {adapter_arrangements}");
     Ok(())
 }
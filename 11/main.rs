@@ -1,82 +1,458 @@
-use std::{error::Error, fs::read_to_string};
+use std::{
+    collections::HashMap,
+    env::args,
+    error::Error,
+    fmt::{self, Display, Formatter},
+    fs::{create_dir_all, read_to_string, write, File},
+    mem::swap,
+    path::PathBuf,
+};
 
+use gif::{Encoder, Frame, Repeat};
 use nalgebra::DMatrix;
 
-fn count_near_occupied_seats(seats: &DMatrix<char>, row: usize, column: usize) -> usize {
-    let (rows, columns) = seats.shape();
-    seats
-        .slice(
-            (
-                if row > 0 { row - 1 } else { row },
-                if column > 0 { column - 1 } else { column },
-            ),
-            (
-                if row > 0 && row < rows - 1 { 3 } else { 2 },
-                if column > 0 && column < columns - 1 {
-                    3
-                } else {
-                    2
-                },
-            ),
-        )
-        .into_iter()
-        .filter(|seat| **seat == '#')
-        .count()
-}
-
-fn count_far_occupied_seats(seats: &DMatrix<char>, row: usize, column: usize) -> usize {
-    let is_direction_occupied = |f: &dyn Fn(isize, isize) -> (isize, isize)| {
-        let mut cur_position = (row as isize, column as isize);
-        loop {
-            let next_position = f(cur_position.0, cur_position.1);
-            if next_position.0 < 0 || next_position.1 < 0 {
-                return 0;
-            }
-            match seats.get((next_position.0 as usize, next_position.1 as usize)) {
-                Some(&'#') => return 1,
-                Some(&'L') | None => return 0,
-                Some(_) => cur_position = next_position,
+/// Which seats around a seat count as its neighbours.
+enum Neighbourhood {
+    /// The eight seats directly around it.
+    Adjacent,
+    /// The first seat visible in each of the eight directions, looking past floor.
+    LineOfSight,
+    /// All seats within the given number of rows and columns.
+    Radius(usize),
+}
+
+impl Neighbourhood {
+    /// Parses `adjacent`, `sight` or `radius=K`.
+    fn parse(value: &str) -> Result<Self, Box<dyn Error>> {
+        match value {
+            "adjacent" => Ok(Self::Adjacent),
+            "sight" => Ok(Self::LineOfSight),
+            _ => {
+                let radius = value
+                    .strip_prefix("radius=")
+                    .ok_or("invalid neighbourhood")?;
+                Ok(Self::Radius(radius.parse()?))
             }
         }
+    }
+}
+
+/// What happens when a neighbour lies beyond the edge of the seat layout.
+enum Edges {
+    /// There is nothing beyond the edge.
+    Bounded,
+    /// The layout wraps around, so the opposite edge continues.
+    Wrap,
+}
+
+impl Edges {
+    /// Parses `bounded` or `wrap`.
+    fn parse(value: &str) -> Result<Self, Box<dyn Error>> {
+        match value {
+            "bounded" => Ok(Self::Bounded),
+            "wrap" => Ok(Self::Wrap),
+            _ => Err("invalid edges".into()),
+        }
+    }
+}
+
+/// The rules of the seating automaton. An empty seat becomes occupied when at most
+/// `birth_threshold` neighbours are occupied, an occupied seat is left when at least
+/// `leave_threshold` neighbours are occupied.
+struct Rules {
+    neighbourhood: Neighbourhood,
+    edges: Edges,
+    birth_threshold: usize,
+    leave_threshold: usize,
+}
+
+impl Rules {
+    const PART_1: Self = Self {
+        neighbourhood: Neighbourhood::Adjacent,
+        edges: Edges::Bounded,
+        birth_threshold: 0,
+        leave_threshold: 4,
     };
 
-    is_direction_occupied(&|row, column| (row - 1, column))
-        + is_direction_occupied(&|row, column| (row - 1, column + 1))
-        + is_direction_occupied(&|row, column| (row, column + 1))
-        + is_direction_occupied(&|row, column| (row + 1, column + 1))
-        + is_direction_occupied(&|row, column| (row + 1, column))
-        + is_direction_occupied(&|row, column| (row + 1, column - 1))
-        + is_direction_occupied(&|row, column| (row, column - 1))
-        + is_direction_occupied(&|row, column| (row - 1, column - 1))
+    const PART_2: Self = Self {
+        neighbourhood: Neighbourhood::LineOfSight,
+        edges: Edges::Bounded,
+        birth_threshold: 0,
+        leave_threshold: 5,
+    };
+
+    /// Moves `distance` steps in the given direction, honouring the edge behaviour.
+    fn offset(
+        &self,
+        (rows, columns): (usize, usize),
+        (row, column): (usize, usize),
+        (row_step, column_step): (isize, isize),
+        distance: isize,
+    ) -> Option<(usize, usize)> {
+        let row = row as isize + row_step * distance;
+        let column = column as isize + column_step * distance;
+        match self.edges {
+            Edges::Bounded if row < 0 || column < 0 => None,
+            Edges::Bounded if row >= rows as isize || column >= columns as isize => None,
+            Edges::Bounded => Some((row as usize, column as usize)),
+            Edges::Wrap => Some((
+                row.rem_euclid(rows as isize) as usize,
+                column.rem_euclid(columns as isize) as usize,
+            )),
+        }
+    }
+
+    /// Returns the positions of all seats that count as neighbours of the given seat.
+    /// Floor is never occupied, so it is left out. On small wrapping layouts several
+    /// directions can reach the same seat, or the seat itself, so every seat is counted once
+    /// and the seat itself never.
+    fn neighbours(&self, seats: &DMatrix<char>, row: usize, column: usize) -> Vec<(usize, usize)> {
+        let radius = match self.neighbourhood {
+            Neighbourhood::Adjacent | Neighbourhood::LineOfSight => 1,
+            Neighbourhood::Radius(radius) => radius as isize,
+        };
+        let directions = (-radius..=radius)
+            .flat_map(|row_step| (-radius..=radius).map(move |column_step| (row_step, column_step)))
+            .filter(|&direction| direction != (0, 0));
+
+        let mut neighbours = match self.neighbourhood {
+            Neighbourhood::Adjacent | Neighbourhood::Radius(_) => directions
+                .filter_map(|direction| self.offset(seats.shape(), (row, column), direction, 1))
+                .filter(|&position| seats[position] != '.')
+                .collect::<Vec<_>>(),
+            Neighbourhood::LineOfSight => {
+                let max_distance = seats.nrows().max(seats.ncols()) as isize;
+                directions
+                    .filter_map(|direction| {
+                        (1..max_distance)
+                            .map_while(|distance| {
+                                self.offset(seats.shape(), (row, column), direction, distance)
+                            })
+                            .find(|&position| seats[position] != '.')
+                    })
+                    .collect()
+            }
+        };
+        neighbours.sort_unstable();
+        neighbours.dedup();
+        neighbours.retain(|&position| position != (row, column));
+        neighbours
+    }
 }
 
+/// The neighbours of every seat, computed once for a layout and a set of rules and stored
+/// as one flat list of linear matrix indices. The neighbours of the seat at index `i` are
+/// `neighbours[offsets[i]..offsets[i + 1]]`.
+struct NeighbourTable {
+    offsets: Vec<usize>,
+    neighbours: Vec<u32>,
+}
+
+impl NeighbourTable {
+    fn new(seats: &DMatrix<char>, rules: &Rules) -> Self {
+        let (mut offsets, mut neighbours) = (vec![0], Vec::new());
+        // Linear indices of a DMatrix are column major.
+        for column in 0..seats.ncols() {
+            for row in 0..seats.nrows() {
+                if seats[(row, column)] != '.' {
+                    neighbours.extend(
+                        rules
+                            .neighbours(seats, row, column)
+                            .into_iter()
+                            .map(|(row, column)| (column * seats.nrows() + row) as u32),
+                    );
+                }
+                offsets.push(neighbours.len());
+            }
+        }
+        Self {
+            offsets,
+            neighbours,
+        }
+    }
+
+    fn of(&self, index: usize) -> &[u32] {
+        &self.neighbours[self.offsets[index]..self.offsets[index + 1]]
+    }
+}
+
+/// Writes the next generation of the `candidates` into `next_seats` and returns the
+/// indices of the seats that changed. Both buffers must agree on all other seats.
 fn iterate_seats_once(
     seats: &DMatrix<char>,
-    f: &dyn Fn(&DMatrix<char>, usize, usize) -> usize,
-) -> DMatrix<char> {
-    let (rows, columns) = seats.shape();
-    DMatrix::from_fn(rows, columns, |row, column| {
-        match seats.get((row, column)) {
-            Some('L') if f(seats, row, column) == 0 => '#',
-            Some('L') => 'L',
-            Some('#') if f(seats, row, column) >= 5 => 'L',
-            Some('#') => '#',
-            Some('.') => '.',
-            _ => unreachable!(),
+    next_seats: &mut DMatrix<char>,
+    neighbour_table: &NeighbourTable,
+    rules: &Rules,
+    candidates: &[u32],
+) -> Vec<u32> {
+    let (current, next) = (seats.as_slice(), next_seats.as_mut_slice());
+    let mut changed = Vec::new();
+    for &index in candidates {
+        let index = index as usize;
+        let count_occupied_neighbours = || {
+            neighbour_table
+                .of(index)
+                .iter()
+                .filter(|&&neighbour| current[neighbour as usize] == '#')
+                .count()
+        };
+        next[index] = match current[index] {
+            'L' if count_occupied_neighbours() <= rules.birth_threshold => '#',
+            '#' if count_occupied_neighbours() >= rules.leave_threshold => 'L',
+            seat => seat,
+        };
+        if next[index] != current[index] {
+            changed.push(index as u32);
         }
-    })
+    }
+    changed
+}
+
+/// How a seat simulation ends up, counted in rounds from the initial layout.
+enum Outcome {
+    Stable { rounds: usize },
+    Cycle { start: usize, period: usize },
 }
 
-fn iterate_seats(seats: &DMatrix<char>, f: &dyn Fn(&DMatrix<char>) -> DMatrix<char>) -> usize {
-    let new_seats = f(seats);
-    if seats == &new_seats {
-        new_seats.into_iter().filter(|seat| **seat == '#').count()
-    } else {
-        iterate_seats(&new_seats, f)
+impl Display for Outcome {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        match self {
+            Self::Stable { rounds } => write!(f, "stable after {rounds} rounds"),
+            Self::Cycle { start, period } => {
+                write!(f, "enters period-{period} cycle at round {start}")
+            }
+        }
     }
 }
 
+/// Mixes a seat index into a pseudo random 64 bit value (SplitMix64). XOR-ing these for
+/// all occupied seats gives a layout hash that can be updated seat by seat.
+fn seat_hash(index: u32) -> u64 {
+    let mut hash = (index as u64).wrapping_add(0x9e3779b97f4a7c15);
+    hash = (hash ^ (hash >> 30)).wrapping_mul(0xbf58476d1ce4e5b9);
+    hash = (hash ^ (hash >> 27)).wrapping_mul(0x94d049bb133111eb);
+    hash ^ (hash >> 31)
+}
+
+/// Iterates the seats until a layout repeats, remembering every layout together with the
+/// round it was first seen in. Layouts are kept as bitsets of occupied seats and grouped
+/// by their hash, so a repeat is only compared against layouts with the same hash. Every
+/// new layout is passed to `on_round`, starting with the initial one. Returns the outcome
+/// together with the last layout.
+///
+/// Two buffers are swapped every round, so only the remembered bitsets get allocated.
+/// Only seats that changed in the last round and their neighbours can change in the next
+/// one (all neighbourhoods are symmetric), so only those are looked at again.
+fn iterate_seats(
+    seats: &DMatrix<char>,
+    rules: &Rules,
+    mut on_round: impl FnMut(&DMatrix<char>) -> Result<(), Box<dyn Error>>,
+) -> Result<(Outcome, DMatrix<char>), Box<dyn Error>> {
+    let neighbour_table = NeighbourTable::new(seats, rules);
+    let mut seen_rounds = HashMap::new();
+    let (mut seats, mut next_seats) = (seats.clone(), seats.clone());
+    let mut candidates = (0..seats.len() as u32).collect::<Vec<_>>();
+    let mut is_candidate = vec![false; seats.len()];
+    let mut occupied = vec![0u64; seats.len().div_ceil(64)];
+    let mut hash = 0;
+    for index in (0..seats.len()).filter(|&index| seats[index] == '#') {
+        occupied[index / 64] ^= 1 << (index % 64);
+        hash ^= seat_hash(index as u32);
+    }
+
+    let mut round = 0;
+    loop {
+        let layouts = seen_rounds.entry(hash).or_insert_with(Vec::new);
+        if let Some(&(start, _)) = layouts.iter().find(|(_, layout)| *layout == occupied) {
+            let outcome = match round - start {
+                1 => Outcome::Stable { rounds: start },
+                period => Outcome::Cycle { start, period },
+            };
+            return Ok((outcome, seats));
+        }
+        layouts.push((round, occupied.clone()));
+        on_round(&seats)?;
+        let changed = iterate_seats_once(
+            &seats,
+            &mut next_seats,
+            &neighbour_table,
+            rules,
+            &candidates,
+        );
+        swap(&mut seats, &mut next_seats);
+
+        candidates.clear();
+        for &index in &changed {
+            occupied[index as usize / 64] ^= 1 << (index % 64);
+            hash ^= seat_hash(index);
+            for &candidate in [index].iter().chain(neighbour_table.of(index as usize)) {
+                if !is_candidate[candidate as usize] {
+                    is_candidate[candidate as usize] = true;
+                    candidates.push(candidate);
+                }
+            }
+        }
+        for &candidate in &candidates {
+            is_candidate[candidate as usize] = false;
+        }
+        round += 1;
+    }
+}
+
+enum FrameFormat {
+    Text,
+    Ppm,
+    Gif,
+}
+
+impl FrameFormat {
+    /// Parses `txt`, `ppm` or `gif`.
+    fn parse(value: &str) -> Result<Self, Box<dyn Error>> {
+        match value {
+            "txt" => Ok(Self::Text),
+            "ppm" => Ok(Self::Ppm),
+            "gif" => Ok(Self::Gif),
+            _ => Err("invalid frame format".into()),
+        }
+    }
+}
+
+/// Writes every generation of a simulation to a directory, either as numbered text or
+/// PPM frames or as a single animated GIF. Images scale every seat up to a square of
+/// `FrameExport::SCALE` pixels and highlight the seats that changed since the last frame.
+struct FrameExport {
+    directory: PathBuf,
+    format: FrameFormat,
+    round: usize,
+    previous: Option<DMatrix<char>>,
+    gif_encoder: Option<Encoder<File>>,
+}
+
+impl FrameExport {
+    const SCALE: usize = 4;
+
+    /// Floor, empty seat, occupied seat, newly emptied seat, newly occupied seat.
+    const PALETTE: [u8; 15] = [
+        0x20, 0x20, 0x20, 0x3c, 0x8c, 0x3c, 0x3c, 0x5a, 0xc8, 0xa0, 0xff, 0xa0, 0xa0, 0xc8, 0xff,
+    ];
+
+    fn new(directory: PathBuf, format: FrameFormat) -> Result<Self, Box<dyn Error>> {
+        create_dir_all(&directory)?;
+        Ok(Self {
+            directory,
+            format,
+            round: 0,
+            previous: None,
+            gif_encoder: None,
+        })
+    }
+
+    /// Returns the palette index of every pixel, row by row.
+    fn pixels(&self, seats: &DMatrix<char>) -> Vec<u8> {
+        let (rows, columns) = seats.shape();
+        let mut pixels = Vec::with_capacity(rows * columns * Self::SCALE * Self::SCALE);
+        for row in 0..rows * Self::SCALE {
+            for column in 0..columns * Self::SCALE {
+                let position = (row / Self::SCALE, column / Self::SCALE);
+                let changed = self
+                    .previous
+                    .as_ref()
+                    .is_some_and(|previous| previous[position] != seats[position]);
+                pixels.push(match (seats[position], changed) {
+                    ('L', false) => 1,
+                    ('#', false) => 2,
+                    ('L', true) => 3,
+                    ('#', true) => 4,
+                    _ => 0,
+                });
+            }
+        }
+        pixels
+    }
+
+    fn write_frame(&mut self, seats: &DMatrix<char>) -> Result<(), Box<dyn Error>> {
+        let (rows, columns) = seats.shape();
+        let (height, width) = (rows * Self::SCALE, columns * Self::SCALE);
+        match self.format {
+            FrameFormat::Text => {
+                let text = seats
+                    .row_iter()
+                    .map(|row| row.iter().collect::<String>() + "\n")
+                    .collect::<String>();
+                let path = self.directory.join(format!("frame_{:05}.txt", self.round));
+                write(path, text)?;
+            }
+            FrameFormat::Ppm => {
+                let mut image = format!("P6\n{width} {height}\n255\n").into_bytes();
+                for pixel in self.pixels(seats) {
+                    let color = pixel as usize * 3;
+                    image.extend_from_slice(&Self::PALETTE[color..color + 3]);
+                }
+                let path = self.directory.join(format!("frame_{:05}.ppm", self.round));
+                write(path, image)?;
+            }
+            FrameFormat::Gif => {
+                let pixels = self.pixels(seats);
+                let (width, height) = (u16::try_from(width)?, u16::try_from(height)?);
+                if self.gif_encoder.is_none() {
+                    let file = File::create(self.directory.join("seats.gif"))?;
+                    let mut encoder = Encoder::new(file, width, height, &Self::PALETTE)?;
+                    encoder.set_repeat(Repeat::Infinite)?;
+                    self.gif_encoder = Some(encoder);
+                }
+                if let Some(encoder) = self.gif_encoder.as_mut() {
+                    encoder.write_frame(&Frame {
+                        delay: 10,
+                        width,
+                        height,
+                        buffer: pixels.into(),
+                        ..Frame::default()
+                    })?;
+                }
+            }
+        }
+        self.previous = Some(seats.clone());
+        self.round += 1;
+        Ok(())
+    }
+}
+
+fn count_occupied_seats(seats: &DMatrix<char>) -> usize {
+    seats.iter().filter(|seat| **seat == '#').count()
+}
+
+/// Returns the occupied seats once the seats stabilize, or describes the cycle they
+/// enter instead, so oscillating layouts still get through both parts.
+fn part_answer(seats: &DMatrix<char>, rules: &Rules) -> Result<String, Box<dyn Error>> {
+    Ok(match iterate_seats(seats, rules, |_| Ok(()))? {
+        (Outcome::Stable { .. }, seats) => count_occupied_seats(&seats).to_string(),
+        (outcome, seats) => format!(
+            "never stable, {outcome}, {} occupied",
+            count_occupied_seats(&seats)
+        ),
+    })
+}
+
 fn main() -> Result<(), Box<dyn Error>> {
+    // Any option runs a custom simulation, starting from the part 1 rules.
+    let (mut rules, mut frame_directory, mut frame_format) = (None, None, FrameFormat::Text);
+    let mut arguments = args().skip(1);
+    while let Some(option) = arguments.next() {
+        let value = arguments
+            .next()
+            .ok_or(format!("missing value for {option}"))?;
+        let custom_rules = rules.get_or_insert(Rules::PART_1);
+        match option.as_str() {
+            "--neighbourhood" => custom_rules.neighbourhood = Neighbourhood::parse(&value)?,
+            "--edges" => custom_rules.edges = Edges::parse(&value)?,
+            "--birth" => custom_rules.birth_threshold = value.parse()?,
+            "--leave" => custom_rules.leave_threshold = value.parse()?,
+            "--frames" => frame_directory = Some(PathBuf::from(value)),
+            "--frame-format" => frame_format = FrameFormat::parse(&value)?,
+            _ => return Err(format!("unknown option {option}").into()),
+        }
+    }
+
     let seat_lines = read_to_string("input.txt")?
         .split("\n")
         .map(ToString::to_string)
@@ -90,18 +466,26 @@ fn main() -> Result<(), Box<dyn Error>> {
 
     println!(
         "2020-12-11 Part 1: {}",
-        iterate_seats(&seats, &|seats| iterate_seats_once(
-            seats,
-            &count_near_occupied_seats
-        ))
+        part_answer(&seats, &Rules::PART_1)?
     );
     println!(
         "2020-12-11 Part 2: {}",
-        iterate_seats(&seats, &|seats| iterate_seats_once(
-            seats,
-            &count_far_occupied_seats
-        ))
+        part_answer(&seats, &Rules::PART_2)?
     );
 
+    if let Some(rules) = rules {
+        let (outcome, seats) = match frame_directory {
+            Some(directory) => {
+                let mut frame_export = FrameExport::new(directory, frame_format)?;
+                iterate_seats(&seats, &rules, |seats| frame_export.write_frame(seats))?
+            }
+            None => iterate_seats(&seats, &rules, |_| Ok(()))?,
+        };
+        println!(
+            "2020-12-11 Custom: {outcome}, {} occupied",
+            count_occupied_seats(&seats)
+        );
+    }
+
     Ok(())
 }
@@ -1,46 +1,224 @@
-use std::{error::Error, fs::read_to_string};
+use std::{
+    env::args,
+    error::Error,
+    fs::{read_to_string, write},
+    iter::once,
+};
 
-use nalgebra::{Matrix2, Vector2};
+use nalgebra::{Rotation2, Vector2};
+
+/// A navigation instruction. Every line is an action letter followed by an integer:
+///
+/// - `N`, `S`, `E`, `W`: move north, south, east or west (part 2: move the waypoint).
+/// - `L`, `R`: turn left or right by any number of degrees
+///   (part 2: rotate the waypoint around the ship).
+/// - `F`: move forward along the heading (part 2: move to the waypoint that many times).
+/// - `H`: reset the heading to the given degrees counterclockwise from east
+///   (part 2: turn the waypoint into that direction, keeping its distance).
+/// - `T`: move the given distance straight towards the waypoint
+///   (part 1: there is no waypoint, so this moves along the heading).
+enum Instruction {
+    Move(Vector2<f64>),
+    Turn(f64),
+    Forward(f64),
+    Heading(f64),
+    Toward(f64),
+}
+
+impl Instruction {
+    fn parse(line: &str) -> Result<Self, Box<dyn Error>> {
+        let action = line.chars().next().ok_or("empty instruction")?;
+        let value = line[action.len_utf8()..]
+            .parse::<i32>()
+            .map_err(|_| format!("invalid value in instruction {line}"))?
+            as f64;
+        Ok(match action {
+            'N' => Self::Move(Vector2::new(0.0, value)),
+            'S' => Self::Move(Vector2::new(0.0, -value)),
+            'E' => Self::Move(Vector2::new(value, 0.0)),
+            'W' => Self::Move(Vector2::new(-value, 0.0)),
+            'L' => Self::Turn(value),
+            'R' => Self::Turn(-value),
+            'F' => Self::Forward(value),
+            'H' => Self::Heading(value),
+            'T' => Self::Toward(value),
+            _ => return Err(format!("unknown instruction {line}").into()),
+        })
+    }
+}
+
+/// Rotates counterclockwise. Quarter turns only swap and negate coordinates, so they stay
+/// exact on whole numbers; any other angle goes through a floating point rotation.
+fn rotate(vector: Vector2<f64>, degrees: f64) -> Vector2<f64> {
+    if degrees % 90.0 != 0.0 {
+        return Rotation2::new(degrees.to_radians()) * vector;
+    }
+    match (degrees / 90.0).rem_euclid(4.0) as u8 {
+        0 => vector,
+        1 => Vector2::new(-vector.y, vector.x),
+        2 => -vector,
+        _ => Vector2::new(vector.y, -vector.x),
+    }
+}
+
+fn manhattan_distance(position: Vector2<f64>) -> f64 {
+    position.x.abs() + position.y.abs()
+}
+
+type State = (Vector2<f64>, Vector2<f64>);
+
+/// Follows the instructions from the initial ship position and heading or waypoint,
+/// collecting the state before the first and after every instruction.
+fn navigate(
+    initial: State,
+    instructions: &[Instruction],
+    step: impl Fn(State, &Instruction) -> State,
+) -> Vec<State> {
+    once(initial)
+        .chain(instructions.iter().scan(initial, |state, inst| {
+            *state = step(*state, inst);
+            Some(*state)
+        }))
+        .collect()
+}
+
+/// Prints at most six decimals, which only matters after turns that are not quarter turns.
+fn format_number(value: f64) -> String {
+    ((value * 1e6).round() / 1e6 + 0.0).to_string()
+}
+
+/// Writes one line per step with the ship position and, if `with_waypoint` is set,
+/// the waypoint relative to the ship.
+fn format_csv(path: &[State], with_waypoint: bool) -> String {
+    let mut csv = if with_waypoint {
+        "step,ship_x,ship_y,waypoint_x,waypoint_y\n".to_string()
+    } else {
+        "step,ship_x,ship_y\n".to_string()
+    };
+    for (step, (ship, waypoint)) in path.iter().enumerate() {
+        csv += &format!("{step},{},{}", format_number(ship.x), format_number(ship.y));
+        if with_waypoint {
+            csv += &format!(
+                ",{},{}",
+                format_number(waypoint.x),
+                format_number(waypoint.y)
+            );
+        }
+        csv += "\n";
+    }
+    csv
+}
+
+/// Renders the ship positions as a polyline with north pointing up, marking
+/// the start in green and the end in red.
+fn format_svg(path: &[State]) -> String {
+    let (min, max) = path.iter().fold(
+        (Vector2::repeat(f64::MAX), Vector2::repeat(f64::MIN)),
+        |(min, max), (ship, _)| (min.inf(ship), max.sup(ship)),
+    );
+    let margin = ((max - min).max() * 0.05).max(1.0).round();
+    let points = path
+        .iter()
+        .map(|(ship, _)| format!("{},{}", format_number(ship.x), format_number(-ship.y)))
+        .collect::<Vec<_>>()
+        .join(" ");
+    let marker = |position: Option<&State>, color: &str| {
+        position.map_or(String::new(), |(ship, _)| {
+            format!(
+                "  <circle cx=\"{}\" cy=\"{}\" r=\"{margin}\" fill=\"{color}\"/>\n",
+                format_number(ship.x),
+                format_number(-ship.y)
+            )
+        })
+    };
+
+    let mut svg = format!(
+        "<svg xmlns=\"http://www.w3.org/2000/svg\" viewBox=\"{} {} {} {}\">\n",
+        format_number(min.x - 2.0 * margin),
+        format_number(-max.y - 2.0 * margin),
+        format_number(max.x - min.x + 4.0 * margin),
+        format_number(max.y - min.y + 4.0 * margin),
+    );
+    svg += &format!(
+        "  <polyline points=\"{points}\" fill=\"none\" stroke=\"black\" \
+        stroke-width=\"1\" vector-effect=\"non-scaling-stroke\"/>\n"
+    );
+    svg += &marker(path.first(), "green");
+    svg += &marker(path.last(), "red");
+    svg + "</svg>\n"
+}
 
 fn main() -> Result<(), Box<dyn Error>> {
+    let (mut exported_part, mut csv_path, mut svg_path) = (2, None, None);
+    let mut arguments = args().skip(1);
+    while let Some(option) = arguments.next() {
+        let value = arguments
+            .next()
+            .ok_or(format!("missing value for {option}"))?;
+        match (option.as_str(), value.as_str()) {
+            ("--path", "1") => exported_part = 1,
+            ("--path", "2") => exported_part = 2,
+            ("--path", _) => return Err("invalid path".into()),
+            ("--csv", _) => csv_path = Some(value),
+            ("--svg", _) => svg_path = Some(value),
+            _ => return Err(format!("unknown option {option}").into()),
+        }
+    }
+
     let instructions = read_to_string("input.txt")?
         .split("\n")
-        .filter_map(|line| {
-            let (inst, param_text) = line.split_at(1);
-            let param = param_text.parse::<i32>().ok()?;
-            Some((inst.chars().last()?, param))
-        })
-        .collect::<Vec<_>>();
+        .filter(|line| !line.is_empty())
+        .map(Instruction::parse)
+        .collect::<Result<Vec<_>, _>>()?;
 
-    let part1 = instructions
-        .iter()
-        .fold((0, 0, 0), |(x, y, dir), (inst, param)| match (inst, dir) {
-            ('N', _) | ('F', 270) => (x, y - param, dir),
-            ('S', _) | ('F', 90) => (x, y + param, dir),
-            ('E', _) | ('F', 0) => (x + param, y, dir),
-            ('W', _) | ('F', 180) => (x - param, y, dir),
-            ('L', _) => (x, y, (360 + dir - param) % 360),
-            ('R', _) => (x, y, (dir + param) % 360),
-            _ => unreachable!(),
-        });
-    println!("2020-12-12 Part 1: {}", part1.0.abs() + part1.1.abs());
-
-    let rotate_right = Matrix2::new(0, 1, -1, 0);
-    let part2 = instructions.iter().fold(
-        (Vector2::new(0, 0), Vector2::new(10, 1)),
-        |(s, w), (inst, param)| match (inst, param) {
-            ('N', _) => (s, w + Vector2::new(0, *param)),
-            ('S', _) => (s, w + Vector2::new(0, -*param)),
-            ('E', _) => (s, w + Vector2::new(*param, 0)),
-            ('W', _) => (s, w + Vector2::new(-*param, 0)),
-            ('L', 90) | ('R', 270) => (s, rotate_right * rotate_right * rotate_right * w),
-            ('L', 180) | ('R', 180) => (s, rotate_right * rotate_right * w),
-            ('L', 270) | ('R', 90) => (s, rotate_right * w),
-            ('F', _) => (s + w * *param, w),
-            _ => unreachable!(),
+    let east = Vector2::new(1.0, 0.0);
+
+    let part1_path = navigate(
+        (Vector2::zeros(), east),
+        &instructions,
+        |(s, h), inst| match inst {
+            Instruction::Move(direction) => (s + direction, h),
+            Instruction::Turn(degrees) => (s, rotate(h, *degrees)),
+            Instruction::Forward(param) | Instruction::Toward(param) => (s + h * *param, h),
+            Instruction::Heading(degrees) => (s, rotate(east, *degrees)),
+        },
+    );
+    let part1 = part1_path.last().ok_or("no path")?;
+    println!(
+        "2020-12-12 Part 1: {}",
+        format_number(manhattan_distance(part1.0))
+    );
+
+    let part2_path = navigate(
+        (Vector2::zeros(), Vector2::new(10.0, 1.0)),
+        &instructions,
+        |(s, w), inst| match inst {
+            Instruction::Move(direction) => (s, w + direction),
+            Instruction::Turn(degrees) => (s, rotate(w, *degrees)),
+            Instruction::Forward(param) => (s + w * *param, w),
+            Instruction::Heading(degrees) => (s, rotate(east, *degrees) * w.norm()),
+            Instruction::Toward(param) => {
+                (s + w.try_normalize(0.0).unwrap_or_default() * *param, w)
+            }
         },
     );
-    println!("2020-12-12 Part 2: {}", part2.0.x.abs() + part2.0.y.abs());
+    let part2 = part2_path.last().ok_or("no path")?;
+    println!(
+        "2020-12-12 Part 2: {}",
+        format_number(manhattan_distance(part2.0))
+    );
+
+    let path = if exported_part == 1 {
+        (&part1_path, false)
+    } else {
+        (&part2_path, true)
+    };
+    if let Some(file) = csv_path {
+        write(file, format_csv(path.0, path.1))?;
+    }
+    if let Some(file) = svg_path {
+        write(file, format_svg(path.0))?;
+    }
 
     Ok(())
 }
@@ -1,8 +1,120 @@
-use std::{error::Error, fs::read_to_string};
+use std::{env::args, error::Error, fs::read_to_string};
 
-use modinverse::modinverse;
+/// Returns `(g, x, y)` with `a * x + b * y = g = gcd(a, b)`.
+fn extended_gcd(a: i128, b: i128) -> (i128, i128, i128) {
+    if b == 0 {
+        (a, 1, 0)
+    } else {
+        let (g, x, y) = extended_gcd(b, a % b);
+        (g, y, x - a / b * y)
+    }
+}
+
+/// Merges the congruences `t ≡ r1 (mod m1)` and `t ≡ r2 (mod m2)` into a single one
+/// modulo `lcm(m1, m2)`. The moduli do not need to be coprime, so this returns `None`
+/// if the two congruences contradict each other. Moduli must be positive.
+fn merge_congruences(
+    (r1, m1): (i128, i128),
+    (r2, m2): (i128, i128),
+) -> Result<Option<(i128, i128)>, Box<dyn Error>> {
+    if m1 <= 0 || m2 <= 0 {
+        return Err(format!("modulus must be positive, got {}", m1.min(m2)).into());
+    }
+    let (g, inverse, _) = extended_gcd(m1, m2);
+    if (r2 - r1) % g != 0 {
+        return Ok(None);
+    }
+    let lcm = (m1 / g).checked_mul(m2).ok_or("modulus overflow")?;
+    let step = ((r2 - r1) / g)
+        .checked_mul(inverse)
+        .ok_or("remainder overflow")?
+        .rem_euclid(m2 / g);
+    let remainder = m1
+        .checked_mul(step)
+        .and_then(|offset| offset.checked_add(r1))
+        .ok_or("remainder overflow")?
+        .rem_euclid(lcm);
+    Ok(Some((remainder, lcm)))
+}
+
+/// Finds the first timestamp at which every `(bus_id, offset)` bus departs `offset`
+/// minutes later, or `None` if that never happens. Bus IDs must be positive.
+fn first_aligned_departure(
+    buses: impl IntoIterator<Item = (i64, i64)>,
+) -> Result<Option<i128>, Box<dyn Error>> {
+    let mut solution = Some((0, 1));
+    for (bus_id, offset) in buses {
+        if let Some(congruence) = solution {
+            if bus_id <= 0 {
+                return Err(format!("invalid bus ID {bus_id}").into());
+            }
+            let bus_id = bus_id as i128;
+            let remainder = (-(offset as i128)).rem_euclid(bus_id);
+            solution = merge_congruences(congruence, (remainder, bus_id))?;
+        }
+    }
+    Ok(solution.map(|(timestamp, _)| timestamp))
+}
+
+/// Returns the number of minutes after which all departures repeat, the lcm of all bus IDs.
+fn schedule_period(bus_ids: impl IntoIterator<Item = i64>) -> Result<i128, Box<dyn Error>> {
+    bus_ids.into_iter().try_fold(1, |period: i128, bus_id| {
+        let (g, _, _) = extended_gcd(period, bus_id as i128);
+        Ok((period / g)
+            .checked_mul(bus_id as i128)
+            .ok_or("period overflow")?)
+    })
+}
+
+/// Returns the first `count` departures of the bus at or after `timestamp`.
+fn next_departures(bus_id: i64, timestamp: i64, count: usize) -> Vec<i64> {
+    let first = (timestamp + bus_id - 1).div_euclid(bus_id) * bus_id;
+    (0..count as i64).map(|n| first + n * bus_id).collect()
+}
+
+/// A schedule query to answer after the puzzle, in the order they were asked for.
+enum Query {
+    /// The next departures of every bus at or after `after`, the earliest timestamp of
+    /// the input by default.
+    Next {
+        after: Option<i64>,
+        count: usize,
+    },
+    /// The first time the given `(bus_id, offset)` buses depart at their offsets.
+    Align(Vec<(i64, i64)>),
+    Period,
+}
 
 fn main() -> Result<(), Box<dyn Error>> {
+    let (mut after, mut queries) = (None, Vec::new());
+    let mut arguments = args().skip(1);
+    while let Some(option) = arguments.next() {
+        let mut value = || {
+            arguments
+                .next()
+                .ok_or(format!("missing value for {option}"))
+        };
+        match option.as_str() {
+            "--after" => after = Some(value()?.parse()?),
+            "--next" => queries.push(Query::Next {
+                after,
+                count: value()?.parse()?,
+            }),
+            "--align" => {
+                let buses = value()?
+                    .split(",")
+                    .map(|bus| {
+                        let (bus_id, offset) = bus.split_once(":").ok_or("expected bus:offset")?;
+                        Ok((bus_id.parse()?, offset.parse()?))
+                    })
+                    .collect::<Result<Vec<_>, Box<dyn Error>>>()?;
+                queries.push(Query::Align(buses));
+            }
+            "--period" => queries.push(Query::Period),
+            _ => return Err(format!("unknown option {option}").into()),
+        }
+    }
+
     let lines = read_to_string("input.txt")?
         .split("\n")
         .map(ToString::to_string)
@@ -10,8 +122,11 @@ fn main() -> Result<(), Box<dyn Error>> {
     let earliest_timestamp = lines[0].parse::<i64>()?;
     let bus_ids = lines[1]
         .split(",")
-        .map(|bus_id| bus_id.parse::<i64>().ok())
-        .collect::<Vec<_>>();
+        .map(|bus_id| match bus_id.parse::<i64>() {
+            Ok(bus_id) if bus_id <= 0 => Err(format!("invalid bus ID {bus_id}")),
+            parsed => Ok(parsed.ok()),
+        })
+        .collect::<Result<Vec<_>, _>>()?;
 
     let best_bus = bus_ids.iter().filter_map(|bus_id| *bus_id).fold(
         (i64::MAX, 0),
@@ -26,23 +141,36 @@ fn main() -> Result<(), Box<dyn Error>> {
     );
     println!("2020-12-13 Part 1: {}", best_bus.0 * best_bus.1);
 
-    let equations = bus_ids
-        .into_iter()
+    let scheduled_buses = bus_ids
+        .iter()
         .enumerate()
-        .filter_map(|(index, bus_id)| bus_id.map(|id| (-(index as i64), id)))
+        .filter_map(|(index, bus_id)| bus_id.map(|bus_id| (bus_id, index as i64)))
         .collect::<Vec<_>>();
 
-    let n = equations.iter().fold(1, |acc, (_, id)| acc * id);
+    match first_aligned_departure(scheduled_buses.iter().copied())? {
+        Some(timestamp) => println!("2020-12-13 Part 2: {timestamp}"),
+        None => println!("2020-12-13 Part 2: no solution"),
+    }
 
-    let chinese_remainder_theorem = equations.into_iter().fold(0, |acc, (a, id)| {
-        let m = n / id;
-        acc + a * m * modinverse(m, id).unwrap_or_default()
-    });
-
-    println!(
-        "2020-12-13 Part 2: {}",
-        (n + (chinese_remainder_theorem % n) % n)
-    );
+    for query in queries {
+        match query {
+            Query::Next { after, count } => {
+                let timestamp = after.unwrap_or(earliest_timestamp);
+                for &(bus_id, _) in &scheduled_buses {
+                    let departures = next_departures(bus_id, timestamp, count);
+                    println!("bus {bus_id}: {departures:?}");
+                }
+            }
+            Query::Align(buses) => match first_aligned_departure(buses)? {
+                Some(timestamp) => println!("aligned at {timestamp}"),
+                None => println!("aligned: no solution"),
+            },
+            Query::Period => {
+                let period = schedule_period(scheduled_buses.iter().map(|&(bus_id, _)| bus_id))?;
+                println!("period {period}");
+            }
+        }
+    }
 
     Ok(())
 }
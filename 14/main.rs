@@ -1,40 +1,51 @@
-use std::{collections::HashMap, error::Error, fs::read_to_string, iter::once};
+use std::{collections::HashMap, env::args, error::Error, fs::read_to_string};
 
 use lazy_static::lazy_static;
 use regex::Regex;
 
 #[derive(Debug)]
 enum Instruction {
-    Mask { and_mask: u64, or_mask: u64 },
-    Mem { address: u64, value: u64 },
+    Mask { and_mask: u128, or_mask: u128 },
+    Mem { address: u128, value: u128 },
 }
 
 impl Instruction {
-    fn parse(instr_text: &str) -> Option<Self> {
+    /// Parses an instruction for a machine with words of `width` bits (1 to 128).
+    /// Masks must have exactly `width` bits, addresses and values must fit into them.
+    fn parse(instr_text: &str, width: u32) -> Result<Self, Box<dyn Error>> {
         lazy_static! {
-            static ref MASK_REGEX: Regex = Regex::new(r"^mask = (?P<mask>[10X]{36})$").unwrap();
+            static ref MASK_REGEX: Regex = Regex::new(r"^mask = (?P<mask>[10X]+)$").unwrap();
             static ref MEM_REGEX: Regex =
                 Regex::new(r"^mem\[(?P<address>\d+)\] = (?P<value>\d+)$").unwrap();
         }
 
         if let Some(mask_captures) = MASK_REGEX.captures(instr_text) {
-            return Some(Self::Mask {
-                and_mask: u64::from_str_radix(&mask_captures["mask"].replace('X', "1"), 2).ok()?,
-                or_mask: u64::from_str_radix(&mask_captures["mask"].replace('X', "0"), 2).ok()?,
+            if mask_captures["mask"].len() != width as usize {
+                return Err(format!("mask does not have {width} bits: {instr_text}").into());
+            }
+            return Ok(Self::Mask {
+                and_mask: u128::from_str_radix(&mask_captures["mask"].replace('X', "1"), 2)?,
+                or_mask: u128::from_str_radix(&mask_captures["mask"].replace('X', "0"), 2)?,
             });
         }
 
         if let Some(mem_captures) = MEM_REGEX.captures(instr_text) {
-            return Some(Self::Mem {
-                address: mem_captures["address"].parse().ok()?,
-                value: mem_captures["value"].parse().ok()?,
-            });
+            let (address, value) = (
+                mem_captures["address"].parse::<u128>()?,
+                mem_captures["value"].parse::<u128>()?,
+            );
+            if address.checked_shr(width).unwrap_or_default() != 0
+                || value.checked_shr(width).unwrap_or_default() != 0
+            {
+                return Err(format!("address or value exceeds {width} bits: {instr_text}").into());
+            }
+            return Ok(Self::Mem { address, value });
         }
 
-        None
+        Err(format!("invalid instruction: {instr_text}").into())
     }
 
-    fn execute_part1(&self, cur_mask: &mut (u64, u64), cur_mem: &mut HashMap<u64, u64>) {
+    fn execute_part1(&self, cur_mask: &mut (u128, u128), cur_mem: &mut HashMap<u128, u128>) {
         match self {
             Self::Mask { and_mask, or_mask } => *cur_mask = (*and_mask, *or_mask),
             Self::Mem { address, value } => {
@@ -43,50 +54,209 @@ impl Instruction {
         }
     }
 
-    fn execute_part2(&self, cur_mask: &mut (u64, u64), cur_mem: &mut HashMap<u64, u64>) {
-        fn get_floating_masks(template: u64) -> Box<dyn Iterator<Item = u64>> {
-            match template.trailing_zeros() {
-                64 => Box::new(once(0)),
-                bit_index => Box::new(
-                    get_floating_masks(template & !(1 << bit_index))
-                        .map(move |mask| mask | 1 << bit_index)
-                        .chain(get_floating_masks(template & !(1 << bit_index))),
-                ),
-            }
-        }
+    fn execute_part2(&self, cur_mask: &mut (u128, u128), cur_mem: &mut FloatingMemory) {
         match self {
             Self::Mask { and_mask, or_mask } => *cur_mask = (*and_mask, *or_mask),
             Self::Mem { address, value } => {
-                let floating_mask_template = cur_mask.0 ^ cur_mask.1;
-                for floating_mask in get_floating_masks(floating_mask_template) {
-                    cur_mem.insert(
-                        ((*address | cur_mask.1) & !floating_mask_template) | floating_mask,
-                        *value,
-                    );
-                }
+                let floating = cur_mask.0 ^ cur_mask.1;
+                cur_mem.write(
+                    AddressPattern {
+                        floating,
+                        fixed: (*address | cur_mask.1) & !floating,
+                    },
+                    *value,
+                );
             }
         }
     }
 }
 
+/// A set of addresses where the `floating` bits can take any value
+/// and all other bits equal those in `fixed`.
+#[derive(Clone, Copy, Debug)]
+struct AddressPattern {
+    floating: u128,
+    fixed: u128,
+}
+
+impl AddressPattern {
+    fn address_count(&self) -> Option<u128> {
+        1u128.checked_shl(self.floating.count_ones())
+    }
+
+    /// Writes the pattern in binary with `X` for floating bits.
+    fn format(&self, width: u32) -> String {
+        (0..width)
+            .rev()
+            .map(
+                |bit| match (self.floating >> bit & 1, self.fixed >> bit & 1) {
+                    (1, _) => 'X',
+                    (_, 1) => '1',
+                    _ => '0',
+                },
+            )
+            .collect()
+    }
+
+    fn intersects(&self, other: &Self) -> bool {
+        let both_fixed = !self.floating & !other.floating;
+        (self.fixed ^ other.fixed) & both_fixed == 0
+    }
+
+    /// Splits the addresses of `self` that are not in `other` into disjoint patterns.
+    ///
+    /// Every bit that floats here but is fixed in `other` splits off the half that
+    /// disagrees with `other`, the half that agrees is narrowed down further.
+    /// What remains after the last such bit lies completely within `other`.
+    fn subtract(&self, other: &Self) -> Vec<Self> {
+        if !self.intersects(other) {
+            return vec![*self];
+        }
+
+        let mut remaining = *self;
+        let mut pieces = Vec::new();
+        let mut split_bits = self.floating & !other.floating;
+        while split_bits != 0 {
+            let bit = 1 << split_bits.trailing_zeros();
+            split_bits &= !bit;
+            remaining.floating &= !bit;
+            pieces.push(Self {
+                floating: remaining.floating,
+                fixed: remaining.fixed | (!other.fixed & bit),
+            });
+            remaining.fixed |= other.fixed & bit;
+        }
+        pieces
+    }
+}
+
+/// Memory of the version 2 decoder that stores every write as an address pattern
+/// instead of expanding it into single addresses. The patterns are kept disjoint by
+/// subtracting every write from all earlier ones, so the sum is exact.
+#[derive(Default)]
+struct FloatingMemory {
+    entries: Vec<(AddressPattern, u128)>,
+}
+
+impl FloatingMemory {
+    fn write(&mut self, pattern: AddressPattern, value: u128) {
+        self.entries = self
+            .entries
+            .iter()
+            .flat_map(|(entry, entry_value)| {
+                entry
+                    .subtract(&pattern)
+                    .into_iter()
+                    .map(move |piece| (piece, *entry_value))
+            })
+            .collect();
+        self.entries.push((pattern, value));
+    }
+
+    fn sum(&self) -> Option<u128> {
+        self.entries
+            .iter()
+            .try_fold(0u128, |sum, (pattern, value)| {
+                sum.checked_add(pattern.address_count()?.checked_mul(*value)?)
+            })
+    }
+}
+
+enum DumpFormat {
+    Decimal,
+    Hex,
+    Binary,
+}
+
+impl DumpFormat {
+    /// Parses `dec`, `hex` or `bin`.
+    fn parse(value: &str) -> Result<Self, Box<dyn Error>> {
+        match value {
+            "dec" => Ok(Self::Decimal),
+            "hex" => Ok(Self::Hex),
+            "bin" => Ok(Self::Binary),
+            _ => Err(format!("invalid dump format {value}").into()),
+        }
+    }
+}
+
+/// Writes a word of `width` bits as decimal, zero padded hex or zero padded binary.
+fn format_word(word: u128, width: u32, format: &DumpFormat) -> String {
+    match format {
+        DumpFormat::Decimal => word.to_string(),
+        DumpFormat::Hex => format!("{word:0digits$x}", digits = width.div_ceil(4) as usize),
+        DumpFormat::Binary => format!("{word:0digits$b}", digits = width as usize),
+    }
+}
+
 fn main() -> Result<(), Box<dyn Error>> {
+    let mut width = 36;
+    let mut dump_format = None;
+    let mut arguments = args().skip(1);
+    while let Some(option) = arguments.next() {
+        let value = arguments
+            .next()
+            .ok_or(format!("missing value for {option}"))?;
+        match option.as_str() {
+            "--width" => width = value.parse()?,
+            "--dump" => dump_format = Some(DumpFormat::parse(&value)?),
+            _ => return Err(format!("unknown option {option}").into()),
+        }
+    }
+    if !(1..=128).contains(&width) {
+        return Err("word width must be between 1 and 128 bits".into());
+    }
+
     let program = read_to_string("input.txt")?
         .split("\n")
-        .filter_map(Instruction::parse)
-        .collect::<Vec<_>>();
+        .filter(|line| !line.is_empty())
+        .map(|line| Instruction::parse(line, width))
+        .collect::<Result<Vec<_>, _>>()?;
 
     let mut mask = (0, 0);
     let mut mem = HashMap::new();
     for instruction in program.iter() {
         instruction.execute_part1(&mut mask, &mut mem);
     }
-    println!("2020-12-14 Part 1: {}", mem.values().sum::<u64>());
+    let sum = mem
+        .values()
+        .try_fold(0u128, |sum, value| sum.checked_add(*value))
+        .ok_or("sum overflow")?;
+    println!("2020-12-14 Part 1: {sum}");
 
-    mem.clear();
+    let mut floating_mem = FloatingMemory::default();
     for instruction in program {
-        instruction.execute_part2(&mut mask, &mut mem);
+        instruction.execute_part2(&mut mask, &mut floating_mem);
+    }
+    println!(
+        "2020-12-14 Part 2: {}",
+        floating_mem.sum().ok_or("sum overflow")?
+    );
+
+    if let Some(format) = dump_format {
+        println!("# version 1");
+        let mut entries = mem.into_iter().collect::<Vec<_>>();
+        entries.sort();
+        for (address, value) in entries {
+            println!(
+                "mem[{}] = {}",
+                format_word(address, width, &format),
+                format_word(value, width, &format)
+            );
+        }
+
+        // Version 2 addresses are patterns, so they are always written in binary.
+        println!("# version 2");
+        let mut entries = floating_mem.entries;
+        entries.sort_by_key(|(pattern, _)| (pattern.fixed, pattern.floating));
+        for (pattern, value) in entries {
+            println!(
+                "mem[{}] = {}",
+                pattern.format(width),
+                format_word(value, width, &format)
+            );
+        }
     }
-    println!("2020-12-14 Part 2: {}", mem.values().sum::<u64>());
 
     Ok(())
 }
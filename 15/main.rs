@@ -1,35 +1,161 @@
 use std::{
-    collections::{HashMap, VecDeque},
+    collections::HashMap,
+    env::args,
     error::Error,
+    fmt::{self, Display, Formatter},
 };
 
-fn main() -> Result<(), Box<dyn Error>> {
-    let mut starting_numbers = VecDeque::from([0, 1, 4, 13, 15, 12, 16]);
+/// The elves' memory game. Remembers the last turn every number was spoken in
+/// (0 for never) in a flat vector, which covers every number spoken after the starting
+/// numbers, since those are always smaller than the turn count. Only starting numbers
+/// beyond that fall back to a hash map.
+struct MemoryGame {
+    starting_numbers: Vec<usize>,
+    last_seen: Vec<u32>,
+    large_last_seen: HashMap<usize, u32>,
+    turn: u32,
+    next_number: usize,
+}
+
+impl MemoryGame {
+    /// Starts a game, reserving the flat vector for `turns` turns. The game can be
+    /// played for longer, larger numbers then just end up in the hash map.
+    fn new(starting_numbers: Vec<usize>, turns: u32) -> Self {
+        Self {
+            next_number: starting_numbers.first().copied().unwrap_or_default(),
+            starting_numbers,
+            last_seen: vec![0; turns as usize],
+            large_last_seen: HashMap::new(),
+            turn: 0,
+        }
+    }
+
+    /// Returns the number spoken in the given turn, counting from 1.
+    fn number_at_turn(mut self, turn: u32) -> Option<usize> {
+        self.nth(turn.checked_sub(1)? as usize)
+    }
 
-    let mut number_map = HashMap::new();
-    let mut last_number = 0;
+    /// Returns the first turn within `turns` turns in which `number` is spoken.
+    fn first_turn_of(self, number: usize, turns: u32) -> Option<u32> {
+        self.take(turns as usize)
+            .position(|spoken| spoken == number)
+            .map(|index| index as u32 + 1)
+    }
 
-    for current_turn in 1..=30000000 {
-        if let Some(n) = starting_numbers.pop_front() {
-            last_number = n;
-            number_map.insert(n, (current_turn, None));
-            continue;
+    /// Collects statistics over the gaps spoken within `turns` turns. After the starting
+    /// numbers, every spoken number is the gap since the last number was spoken before.
+    fn gap_statistics(self, turns: u32) -> GapStatistics {
+        let skipped = self.starting_numbers.len();
+        let mut statistics = GapStatistics::default();
+        for gap in self.take(turns as usize).skip(skipped) {
+            statistics.count += 1;
+            statistics.sum += gap as u64;
+            statistics.max = statistics.max.max(gap);
+            if gap == 0 {
+                statistics.new_numbers += 1;
+            }
         }
+        statistics
+    }
+}
+
+impl Iterator for MemoryGame {
+    type Item = usize;
 
-        let (n, maybe_pn) = number_map[&last_number];
-        last_number = maybe_pn.map(|pn| n - pn).unwrap_or_default();
-        number_map
-            .entry(last_number)
-            .and_modify(|(n, pn)| {
-                *pn = Some(*n);
-                *n = current_turn;
-            })
-            .or_insert((current_turn, None));
-
-        if current_turn == 2020 {
-            println!("2020-12-15 Part 1: {last_number}")
+    /// Speaks the next number and returns it.
+    fn next(&mut self) -> Option<usize> {
+        let number = self.next_number;
+        self.turn = self.turn.checked_add(1)?;
+
+        let last_seen = match self.last_seen.get_mut(number) {
+            Some(last_seen) => last_seen,
+            None => self.large_last_seen.entry(number).or_default(),
+        };
+        let previous_turn = std::mem::replace(last_seen, self.turn);
+
+        self.next_number = match self.starting_numbers.get(self.turn as usize) {
+            Some(&starting_number) => starting_number,
+            None if previous_turn == 0 => 0,
+            None => (self.turn - previous_turn) as usize,
+        };
+        Some(number)
+    }
+}
+
+#[derive(Default)]
+struct GapStatistics {
+    count: u64,
+    sum: u64,
+    max: usize,
+    new_numbers: u64,
+}
+
+impl Display for GapStatistics {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{} gaps, {} new numbers, max gap {}, mean gap {:.2}",
+            self.count,
+            self.new_numbers,
+            self.max,
+            self.sum as f64 / self.count.max(1) as f64
+        )
+    }
+}
+
+fn main() -> Result<(), Box<dyn Error>> {
+    let mut starting_numbers = vec![0, 1, 4, 13, 15, 12, 16];
+    let (mut turns, mut first_number, mut show_gaps) = (None, None, false);
+    let mut arguments = args().skip(1);
+    while let Some(option) = arguments.next() {
+        let mut value = || {
+            arguments
+                .next()
+                .ok_or(format!("missing value for {option}"))
+        };
+        match option.as_str() {
+            "--start" => {
+                starting_numbers = value()?
+                    .split(",")
+                    .map(str::parse)
+                    .collect::<Result<_, _>>()?
+            }
+            "--turns" => turns = Some(value()?.parse()?),
+            "--first" => first_number = Some(value()?.parse()?),
+            "--gaps" => show_gaps = true,
+            _ => return Err(format!("unknown option {option}").into()),
+        }
+    }
+    let new_game = |turns| MemoryGame::new(starting_numbers.clone(), turns);
+
+    if turns.is_some() || first_number.is_some() || show_gaps {
+        let turns = turns.unwrap_or(30000000);
+        match first_number {
+            Some(number) => match new_game(turns).first_turn_of(number, turns) {
+                Some(turn) => println!("2020-12-15 First {number}: turn {turn}"),
+                None => println!("2020-12-15 First {number}: not within {turns} turns"),
+            },
+            None => println!(
+                "2020-12-15 Turn {turns}: {}",
+                new_game(turns).number_at_turn(turns).ok_or("no turn")?
+            ),
+        }
+        if show_gaps {
+            println!("2020-12-15 Gaps: {}", new_game(turns).gap_statistics(turns));
         }
+        return Ok(());
     }
 
-    Ok(println!("2020-12-15 Part 2: {last_number}"))
+    println!(
+        "2020-12-15 Part 1: {}",
+        new_game(2020).number_at_turn(2020).ok_or("no turn")?
+    );
+    println!(
+        "2020-12-15 Part 2: {}",
+        new_game(30000000)
+            .number_at_turn(30000000)
+            .ok_or("no turn")?
+    );
+
+    Ok(())
 }
@@ -1,15 +1,89 @@
 use std::{
-    collections::{HashMap, HashSet},
+    collections::{HashMap, HashSet, VecDeque},
+    env::args,
     error::Error,
-    fs::read_to_string,
+    fs::{read_to_string, write},
     iter::once,
 };
 
 use regex::Regex;
 
+/// The values allowed for a ticket field, as sorted, disjoint and non-adjacent
+/// inclusive intervals, so checking a value is a binary search.
+#[derive(Clone, Debug, Default)]
+struct ValueSet {
+    intervals: Vec<(u32, u32)>,
+}
+
+impl ValueSet {
+    /// Parses constraints joined by `or`, each of which is either an inclusive range
+    /// `a-b`, a single value `a` or an open-ended bound `>= a`, `> a`, `<= a` or `< a`.
+    fn parse(input: &str) -> Result<Self, Box<dyn Error>> {
+        let intervals = input
+            .split(" or ")
+            .map(|constraint| {
+                let constraint = constraint.trim();
+                let bound = |prefix: &str| {
+                    constraint
+                        .strip_prefix(prefix)
+                        .map(|value| value.trim().parse::<u32>())
+                };
+                Ok(if let Some(value) = bound(">=") {
+                    (value?, u32::MAX)
+                } else if let Some(value) = bound("<=") {
+                    (0, value?)
+                } else if let Some(value) = bound(">") {
+                    (value?.checked_add(1).ok_or("empty constraint")?, u32::MAX)
+                } else if let Some(value) = bound("<") {
+                    (0, value?.checked_sub(1).ok_or("empty constraint")?)
+                } else if let Some((from, to)) = constraint.split_once("-") {
+                    let (from, to) = (from.parse()?, to.parse()?);
+                    if from > to {
+                        return Err(format!("reversed range {constraint}").into());
+                    }
+                    (from, to)
+                } else {
+                    let value = constraint.parse()?;
+                    (value, value)
+                })
+            })
+            .collect::<Result<Vec<_>, Box<dyn Error>>>()?;
+        Ok(Self::from_intervals(intervals))
+    }
+
+    fn from_intervals(mut intervals: Vec<(u32, u32)>) -> Self {
+        intervals.sort();
+        let mut merged = Vec::<(u32, u32)>::with_capacity(intervals.len());
+        for (from, to) in intervals {
+            match merged.last_mut() {
+                Some((_, last_to)) if from <= last_to.saturating_add(1) => {
+                    *last_to = (*last_to).max(to)
+                }
+                _ => merged.push((from, to)),
+            }
+        }
+        Self { intervals: merged }
+    }
+
+    fn union<'a>(sets: impl IntoIterator<Item = &'a Self>) -> Self {
+        Self::from_intervals(
+            sets.into_iter()
+                .flat_map(|set| set.intervals.iter().copied())
+                .collect(),
+        )
+    }
+
+    fn contains(&self, value: u32) -> bool {
+        let index = self.intervals.partition_point(|(_, to)| *to < value);
+        self.intervals
+            .get(index)
+            .is_some_and(|(from, _)| *from <= value)
+    }
+}
+
 #[derive(Debug)]
 struct TicketData {
-    fields: HashMap<String, (u32, u32, u32, u32)>,
+    fields: HashMap<String, ValueSet>,
     ticket: Vec<u32>,
     other_tickets: Vec<Vec<u32>>,
 }
@@ -18,17 +92,20 @@ impl TicketData {
     fn parse(input: &str) -> Result<Self, Box<dyn Error>> {
         let sections = input.split("\n\n").collect::<Vec<_>>();
 
-        let field_regex = Regex::new(
-            r"^(?P<name>[^:]+): (?P<r11>\d+)-(?P<r12>\d+) or (?P<r21>\d+)-(?P<r22>\d+)$",
-        )?;
-        let fields = HashMap::from_iter(sections[0].split("\n").filter_map(|field_line| {
-            let field_match = field_regex.captures(field_line)?;
-            let r = |name: &str| field_match[name].parse().ok();
-            Some((
-                field_match["name"].to_string(),
-                (r("r11")?, r("r12")?, r("r21")?, r("r22")?),
-            ))
-        }));
+        let field_regex = Regex::new(r"^(?P<name>[^:]+): (?P<constraints>.+)$")?;
+        let fields = sections[0]
+            .split("\n")
+            .map(|field_line| {
+                let field_match = field_regex
+                    .captures(field_line)
+                    .ok_or_else(|| format!("invalid field: {field_line}"))?;
+                Ok((
+                    field_match["name"].to_string(),
+                    ValueSet::parse(&field_match["constraints"])
+                        .map_err(|error| format!("invalid field: {field_line}: {error}"))?,
+                ))
+            })
+            .collect::<Result<HashMap<_, _>, Box<dyn Error>>>()?;
 
         let ticket = sections[1]
             .split("\n")
@@ -52,17 +129,342 @@ impl TicketData {
     }
 }
 
+enum FieldAssignment {
+    /// The column of every field.
+    Unique(Vec<usize>),
+    /// The number of different ways to assign all fields, `None` if there are more
+    /// than `ASSIGNMENT_LIMIT`.
+    Ambiguous(Option<u128>),
+    /// A set of fields that have fewer candidate columns between them than fields.
+    Impossible(Vec<usize>),
+}
+
+/// Finds a maximum matching between fields and their candidate columns with Hopcroft-Karp.
+/// Returns the matched column of every field.
+fn hopcroft_karp(candidates: &[Vec<usize>], columns: usize) -> Vec<Option<usize>> {
+    fn augment(
+        field: usize,
+        candidates: &[Vec<usize>],
+        distances: &mut [usize],
+        field_matches: &mut [Option<usize>],
+        column_matches: &mut [Option<usize>],
+    ) -> bool {
+        for &column in &candidates[field] {
+            let next_field_matches = match column_matches[column] {
+                None => true,
+                Some(next_field) => {
+                    distances[next_field] == distances[field] + 1
+                        && augment(
+                            next_field,
+                            candidates,
+                            distances,
+                            field_matches,
+                            column_matches,
+                        )
+                }
+            };
+            if next_field_matches {
+                field_matches[field] = Some(column);
+                column_matches[column] = Some(field);
+                return true;
+            }
+        }
+        distances[field] = usize::MAX;
+        false
+    }
+
+    let mut field_matches = vec![None; candidates.len()];
+    let mut column_matches = vec![None; columns];
+    loop {
+        // Layer the fields by their alternating path distance from the unmatched ones.
+        let mut distances = vec![usize::MAX; candidates.len()];
+        let mut queue = VecDeque::new();
+        for field in 0..candidates.len() {
+            if field_matches[field].is_none() {
+                distances[field] = 0;
+                queue.push_back(field);
+            }
+        }
+        let mut found_free_column = false;
+        while let Some(field) = queue.pop_front() {
+            for &column in &candidates[field] {
+                match column_matches[column] {
+                    None => found_free_column = true,
+                    Some(next_field) if distances[next_field] == usize::MAX => {
+                        distances[next_field] = distances[field] + 1;
+                        queue.push_back(next_field);
+                    }
+                    Some(_) => (),
+                }
+            }
+        }
+        if !found_free_column {
+            return field_matches;
+        }
+
+        for field in 0..candidates.len() {
+            if field_matches[field].is_none() {
+                augment(
+                    field,
+                    candidates,
+                    &mut distances,
+                    &mut field_matches,
+                    &mut column_matches,
+                );
+            }
+        }
+    }
+}
+
+/// Counting stops beyond this many assignments of fields to columns.
+const ASSIGNMENT_LIMIT: u128 = 1_000_000;
+
+/// Splits the fields into groups that share no candidate columns with each other,
+/// so the assignments of every group can be counted on their own.
+fn connected_fields(candidates: &[Vec<usize>], columns: usize) -> Vec<Vec<usize>> {
+    let mut column_fields = vec![Vec::new(); columns];
+    for (field, field_columns) in candidates.iter().enumerate() {
+        for &column in field_columns {
+            column_fields[column].push(field);
+        }
+    }
+
+    let mut visited_fields = vec![false; candidates.len()];
+    let mut groups = Vec::new();
+    for first_field in 0..candidates.len() {
+        if visited_fields[first_field] {
+            continue;
+        }
+        visited_fields[first_field] = true;
+        let mut group = vec![first_field];
+        let mut index = 0;
+        while index < group.len() {
+            for &column in &candidates[group[index]] {
+                for &field in &column_fields[column] {
+                    if !visited_fields[field] {
+                        visited_fields[field] = true;
+                        group.push(field);
+                    }
+                }
+            }
+            index += 1;
+        }
+        groups.push(group);
+    }
+    groups
+}
+
+/// Counts all perfect assignments of fields to their candidate columns, or returns `None`
+/// if there are more than `ASSIGNMENT_LIMIT`. Every group of connected fields is counted
+/// by backtracking, trying the fields with the fewest candidates first and remembering
+/// the count for every set of used columns, since the fields left to assign only depend
+/// on how many are used. The counts of all groups multiply.
+fn count_assignments(candidates: &[Vec<usize>], columns: usize) -> Option<u128> {
+    fn count(
+        fields: &[&Vec<usize>],
+        used_columns: u64,
+        memo: &mut HashMap<u64, Option<u128>>,
+    ) -> Option<u128> {
+        let Some(field) = fields.get(used_columns.count_ones() as usize) else {
+            return Some(1);
+        };
+        if let Some(&known) = memo.get(&used_columns) {
+            return known;
+        }
+        let total = field
+            .iter()
+            .filter(|&&column| used_columns & 1 << column == 0)
+            .try_fold(0u128, |total, &column| {
+                total
+                    .checked_add(count(fields, used_columns | 1 << column, memo)?)
+                    .filter(|&total| total <= ASSIGNMENT_LIMIT)
+            });
+        memo.insert(used_columns, total);
+        total
+    }
+
+    connected_fields(candidates, columns)
+        .into_iter()
+        .try_fold(1u128, |total, group| {
+            let mut fields = group
+                .into_iter()
+                .map(|field| &candidates[field])
+                .collect::<Vec<_>>();
+            fields.sort_by_key(|field| field.len());
+            total
+                .checked_mul(count(&fields, 0, &mut HashMap::new())?)
+                .filter(|&total| total <= ASSIGNMENT_LIMIT)
+        })
+}
+
+/// Assigns every field a column. Fields without a column in the maximum matching prove
+/// the assignment impossible: all fields reachable from one over alternating paths share
+/// fewer columns than there are of them. Otherwise all assignments are counted.
+fn assign_fields(
+    candidates: &[Vec<usize>],
+    columns: usize,
+) -> Result<FieldAssignment, Box<dyn Error>> {
+    if columns > 64 {
+        return Err("too many ticket fields".into());
+    }
+
+    let field_matches = hopcroft_karp(candidates, columns);
+    if let Some(unmatched_field) = field_matches.iter().position(Option::is_none) {
+        let mut column_matches = vec![None; columns];
+        for (field, column) in field_matches.iter().enumerate() {
+            if let Some(column) = column {
+                column_matches[*column] = Some(field);
+            }
+        }
+
+        let mut conflicting_fields = vec![unmatched_field];
+        let mut visited_columns = HashSet::new();
+        let mut index = 0;
+        while index < conflicting_fields.len() {
+            for &column in &candidates[conflicting_fields[index]] {
+                if visited_columns.insert(column) {
+                    conflicting_fields.extend(column_matches[column]);
+                }
+            }
+            index += 1;
+        }
+        conflicting_fields.sort();
+        return Ok(FieldAssignment::Impossible(conflicting_fields));
+    }
+
+    Ok(match count_assignments(candidates, columns) {
+        Some(1) => FieldAssignment::Unique(field_matches.into_iter().flatten().collect()),
+        count => FieldAssignment::Ambiguous(count),
+    })
+}
+
+type RejectedTicket<'a> = (usize, &'a Vec<u32>, Vec<u32>);
+
+fn quote_csv(text: &str) -> String {
+    if text.contains([',', '"', '\n']) {
+        format!("\"{}\"", text.replace('"', "\"\""))
+    } else {
+        text.to_string()
+    }
+}
+
+fn quote_json(text: &str) -> String {
+    let mut quoted = "\"".to_string();
+    for c in text.chars() {
+        match c {
+            '"' => quoted += "\\\"",
+            '\\' => quoted += "\\\\",
+            c if c.is_control() => quoted += &format!("\\u{:04x}", c as u32),
+            c => quoted.push(c),
+        }
+    }
+    quoted + "\""
+}
+
+/// Writes the decoded tickets as CSV with a header row of field names, ordered by column.
+/// The first column names the ticket, `own` or the index of the nearby ticket.
+fn format_tickets_csv(columns: &[(&str, usize)], tickets: &[(String, &Vec<u32>)]) -> String {
+    let mut csv = once("ticket".to_string())
+        .chain(columns.iter().map(|(field_name, _)| quote_csv(field_name)))
+        .collect::<Vec<_>>()
+        .join(",");
+    csv += "\n";
+    for (label, ticket) in tickets {
+        csv += &once(label.clone())
+            .chain(
+                columns
+                    .iter()
+                    .map(|(_, column)| ticket[*column].to_string()),
+            )
+            .collect::<Vec<_>>()
+            .join(",");
+        csv += "\n";
+    }
+    csv
+}
+
+/// Writes the rejected nearby tickets as CSV with their index, all values and the
+/// invalid ones, both separated by spaces.
+fn format_rejected_csv(rejected_tickets: &[RejectedTicket]) -> String {
+    let join = |values: &[u32]| {
+        values
+            .iter()
+            .map(ToString::to_string)
+            .collect::<Vec<_>>()
+            .join(" ")
+    };
+    let mut csv = "ticket,values,invalid_values\n".to_string();
+    for (index, ticket, invalid_values) in rejected_tickets {
+        csv += &format!("{index},{},{}\n", join(ticket), join(invalid_values));
+    }
+    csv
+}
+
+/// Writes the decoded tickets as JSON objects keyed by field name, together with
+/// the rejected nearby tickets and their invalid values.
+fn format_tickets_json(
+    columns: &[(&str, usize)],
+    tickets: &[(String, &Vec<u32>)],
+    rejected_tickets: &[RejectedTicket],
+) -> String {
+    let record = |ticket: &Vec<u32>| {
+        let fields = columns
+            .iter()
+            .map(|(field_name, column)| format!("{}: {}", quote_json(field_name), ticket[*column]))
+            .collect::<Vec<_>>();
+        format!("{{{}}}", fields.join(", "))
+    };
+    let list = |values: &[u32]| format!("{values:?}");
+
+    let decoded = tickets
+        .iter()
+        .map(|(label, ticket)| {
+            format!(
+                "    {{\"ticket\": {}, \"fields\": {}}}",
+                quote_json(label),
+                record(ticket)
+            )
+        })
+        .collect::<Vec<_>>();
+    let rejected = rejected_tickets
+        .iter()
+        .map(|(index, ticket, invalid_values)| {
+            format!(
+                "    {{\"ticket\": {index}, \"values\": {}, \"invalid_values\": {}}}",
+                list(ticket),
+                list(invalid_values)
+            )
+        })
+        .collect::<Vec<_>>();
+    format!(
+        "{{\n  \"tickets\": [\n{}\n  ],\n  \"rejected\": [\n{}\n  ]\n}}\n",
+        decoded.join(",\n"),
+        rejected.join(",\n")
+    )
+}
+
 fn main() -> Result<(), Box<dyn Error>> {
+    let (mut csv_path, mut json_path, mut rejected_path) = (None, None, None);
+    let mut arguments = args().skip(1);
+    while let Some(option) = arguments.next() {
+        let mut value = || {
+            arguments
+                .next()
+                .ok_or(format!("missing value for {option}"))
+        };
+        match option.as_str() {
+            "--csv" => csv_path = Some(value()?),
+            "--json" => json_path = Some(value()?),
+            "--rejected" => rejected_path = Some(value()?),
+            _ => return Err(format!("unknown option {option}").into()),
+        }
+    }
+
     let input = read_to_string("input.txt")?;
     let ticket_data = TicketData::parse(&input)?;
 
-    let check_range: Box<dyn Fn(u32) -> bool> = ticket_data
-        .fields
-        .values()
-        .map(|(r11, r12, r21, r22)| |n: u32| n >= *r11 && n <= *r12 || n >= *r21 && n <= *r22)
-        .fold(Box::new(|_| false), |chain, f| {
-            Box::new(move |n| f(n) || chain(n))
-        });
+    let any_field_values = ValueSet::union(ticket_data.fields.values());
+    let check_range = |n: u32| any_field_values.contains(n);
 
     let error_rate = ticket_data
         .other_tickets
@@ -78,52 +480,105 @@ fn main() -> Result<(), Box<dyn Error>> {
         .filter(|other_ticket| other_ticket.iter().all(|n| check_range(*n)))
         .collect::<Vec<_>>();
 
-    let get_matching_field_indexes = |field_name: &str, known_indexes: &HashSet<u32>| {
-        let (r11, r12, r21, r22) = ticket_data.fields.get(field_name).unwrap();
-        (0..ticket_data.ticket.len() as u32)
-            .filter(|index| !known_indexes.contains(index))
-            .filter(|index| {
-                once(ticket_data.ticket[*index as usize])
-                    .chain(
-                        valid_other_tickets
-                            .iter()
-                            .map(|other_ticket| other_ticket[*index as usize]),
-                    )
-                    .all(|n| n >= *r11 && n <= *r12 || n >= *r21 && n <= *r22)
-            })
-            .collect::<Vec<_>>()
+    let field_names = {
+        let mut field_names = ticket_data.fields.keys().collect::<Vec<_>>();
+        field_names.sort();
+        field_names
     };
-
-    let find_unique_field_index = |field_names: &HashSet<&String>, known_indexes: &HashSet<u32>| {
-        field_names.iter().find_map(|field_name| {
-            let indexes = get_matching_field_indexes(field_name, known_indexes);
-            if indexes.len() == 1 {
-                Some(((*field_name).clone(), indexes[0]))
-            } else {
-                None
-            }
+    let candidates = field_names
+        .iter()
+        .map(|field_name| {
+            let field_values = &ticket_data.fields[*field_name];
+            (0..ticket_data.ticket.len())
+                .filter(|index| {
+                    once(ticket_data.ticket[*index])
+                        .chain(
+                            valid_other_tickets
+                                .iter()
+                                .map(|other_ticket| other_ticket[*index]),
+                        )
+                        .all(|n| field_values.contains(n))
+                })
+                .collect::<Vec<_>>()
         })
-    };
-
-    let mut fields_to_identify = HashSet::<_>::from_iter(ticket_data.fields.keys());
-    let mut known_fields = HashMap::new();
+        .collect::<Vec<_>>();
 
-    while !fields_to_identify.is_empty() {
-        if let Some((field_name, field_index)) = find_unique_field_index(
-            &fields_to_identify,
-            &known_fields.values().copied().collect::<HashSet<_>>(),
-        ) {
-            fields_to_identify.remove(&field_name);
-            known_fields.insert(field_name, field_index);
+    let mut decoded_tickets = vec![("own".to_string(), &ticket_data.ticket)];
+    let mut rejected_tickets = Vec::new();
+    for (index, other_ticket) in ticket_data.other_tickets.iter().enumerate() {
+        let invalid_values = other_ticket
+            .iter()
+            .copied()
+            .filter(|n| !check_range(*n))
+            .collect::<Vec<_>>();
+        if invalid_values.is_empty() {
+            decoded_tickets.push((index.to_string(), other_ticket));
+        } else {
+            rejected_tickets.push((index, other_ticket, invalid_values));
         }
     }
 
+    if let Some(path) = rejected_path {
+        write(path, format_rejected_csv(&rejected_tickets))?;
+    }
+    // Decoding the tickets needs the column of every field.
+    let undecodable = || -> Result<(), Box<dyn Error>> {
+        if csv_path.is_some() || json_path.is_some() {
+            return Err("cannot decode tickets without a unique field assignment".into());
+        }
+        Ok(())
+    };
+
+    let known_fields = match assign_fields(&candidates, ticket_data.ticket.len())? {
+        FieldAssignment::Unique(columns) => field_names
+            .iter()
+            .zip(columns)
+            .map(|(field_name, column)| ((*field_name).clone(), column))
+            .collect::<HashMap<_, _>>(),
+        FieldAssignment::Ambiguous(Some(count)) => {
+            println!("2020-12-16 Part 2: ambiguous: {count} valid assignments");
+            return undecodable();
+        }
+        FieldAssignment::Ambiguous(None) => {
+            println!(
+                "2020-12-16 Part 2: ambiguous: more than {ASSIGNMENT_LIMIT} valid assignments"
+            );
+            return undecodable();
+        }
+        FieldAssignment::Impossible(conflicting_fields) => {
+            let conflicting_names = conflicting_fields
+                .into_iter()
+                .map(|field| field_names[field].as_str())
+                .collect::<Vec<_>>();
+            println!(
+                "2020-12-16 Part 2: impossible: fields {} conflict",
+                conflicting_names.join(", ")
+            );
+            return undecodable();
+        }
+    };
+
     let departure_product = known_fields
         .iter()
         .filter(|(field_name, _)| field_name.starts_with("departure"))
-        .map(|(_, field_index)| ticket_data.ticket[*field_index as usize])
+        .map(|(_, field_index)| ticket_data.ticket[*field_index])
         .fold(1, |acc, value| acc * value as u64);
     println!("2020-12-16 Part 2: {departure_product}");
 
+    let mut columns = known_fields
+        .iter()
+        .map(|(field_name, column)| (field_name.as_str(), *column))
+        .collect::<Vec<_>>();
+    columns.sort_by_key(|(_, column)| *column);
+
+    if let Some(path) = csv_path {
+        write(path, format_tickets_csv(&columns, &decoded_tickets))?;
+    }
+    if let Some(path) = json_path {
+        write(
+            path,
+            format_tickets_json(&columns, &decoded_tickets, &rejected_tickets),
+        )?;
+    }
     Ok(())
 }
@@ -1,47 +1,273 @@
-use std::{collections::HashSet, error::Error, fs::read_to_string};
-
-fn neighbor_indexes(
-    (x, y, z, w): (i32, i32, i32, i32),
-    dim_4: bool,
-) -> impl Iterator<Item = (i32, i32, i32, i32)> {
-    (-1..=1).flat_map(move |dx| {
-        (-1..=1).flat_map(move |dy| {
-            (-1..=1).flat_map(move |dz| {
-                (if dim_4 { -1..=1 } else { 0..=0 }).filter_map(move |dw| {
-                    if dx != 0 || dy != 0 || dz != 0 || dw != 0 {
-                        Some((x + dx, y + dy, z + dz, w + dw))
-                    } else {
-                        None
-                    }
+use std::{
+    collections::{BTreeMap, HashMap, HashSet},
+    env::args,
+    error::Error,
+    fs::{create_dir_all, read_to_string, write},
+    path::Path,
+};
+
+/// The highest number of dimensions supported. Coordinates always have this many axes,
+/// only the first `dimensions` of them ever leave 0.
+const MAX_DIMENSIONS: usize = 8;
+
+type Coord = [i32; MAX_DIMENSIONS];
+
+/// Life-like rules in `B3/S23` notation: a dead cell with a neighbor count listed after `B`
+/// is born, a live cell with a neighbor count listed after `S` survives. Counts are single
+/// digits, or separated by commas if any of them needs more than one (`B3/S2,3,10`).
+struct Rules {
+    birth: Vec<usize>,
+    survival: Vec<usize>,
+}
+
+impl Rules {
+    fn parse(input: &str) -> Result<Self, Box<dyn Error>> {
+        let parse_counts = |counts: &str| -> Result<Vec<usize>, Box<dyn Error>> {
+            if counts.contains(',') {
+                Ok(counts
+                    .split(",")
+                    .map(str::parse)
+                    .collect::<Result<_, _>>()?)
+            } else {
+                counts
+                    .chars()
+                    .map(|count| Ok(count.to_digit(10).ok_or("invalid count")? as usize))
+                    .collect()
+            }
+        };
+        let (birth, survival) = input.split_once("/").ok_or("expected B.../S...")?;
+        let birth = parse_counts(birth.strip_prefix("B").ok_or("expected B...")?)?;
+        if birth.contains(&0) {
+            return Err("B0 would fill all of space".into());
+        }
+        Ok(Self {
+            birth,
+            survival: parse_counts(survival.strip_prefix("S").ok_or("expected S...")?)?,
+        })
+    }
+}
+
+impl Default for Rules {
+    fn default() -> Self {
+        Self {
+            birth: vec![3],
+            survival: vec![2, 3],
+        }
+    }
+}
+
+/// Returns the offsets to all neighbors in the given number of dimensions.
+fn neighbor_offsets(dimensions: usize) -> Vec<Coord> {
+    (0..dimensions)
+        .fold(vec![[0; MAX_DIMENSIONS]], |offsets, axis| {
+            offsets
+                .into_iter()
+                .flat_map(|offset| {
+                    (-1..=1).map(move |delta| {
+                        let mut offset = offset;
+                        offset[axis] = delta;
+                        offset
+                    })
                 })
-            })
+                .collect()
         })
-    })
+        .into_iter()
+        .filter(|offset| offset.iter().any(|delta| *delta != 0))
+        .collect()
 }
 
-fn alive_neighbors(
-    map: &HashSet<(i32, i32, i32, i32)>,
-    coord: (i32, i32, i32, i32),
-    dim_4: bool,
-) -> usize {
-    map.intersection(&neighbor_indexes(coord, dim_4).collect())
+fn add(coord: &Coord, offset: &Coord) -> Coord {
+    let mut sum = *coord;
+    sum.iter_mut()
+        .zip(offset)
+        .for_each(|(axis, delta)| *axis += delta);
+    sum
+}
+
+/// The first axis mirrored by the symmetric mode. The puzzle starts in a single 2D slice,
+/// so every axis after x and y is mirror symmetric around 0 in every generation.
+const FIRST_MIRRORED_AXIS: usize = 2;
+
+/// Whether a cell lies in the canonical region stored by the symmetric mode, where all
+/// mirrored axes are non-negative.
+fn is_canonical(coord: &Coord) -> bool {
+    coord[FIRST_MIRRORED_AXIS..].iter().all(|axis| *axis >= 0)
+}
+
+/// The number of cells a canonical cell stands for, one per mirror image.
+fn weight(coord: &Coord) -> usize {
+    1 << coord[FIRST_MIRRORED_AXIS..]
+        .iter()
+        .filter(|axis| **axis != 0)
         .count()
 }
 
-fn iterate_once(map: &HashSet<(i32, i32, i32, i32)>, dim_4: bool) -> HashSet<(i32, i32, i32, i32)> {
-    let remaining_cells = map.iter().copied().filter(|coord| {
-        let alive_neighbors = alive_neighbors(map, *coord, dim_4);
-        alive_neighbors >= 2 && alive_neighbors <= 3
-    });
-    let born_cells = map
+/// Returns the canonical cell together with those of its mirror images that can still
+/// neighbor a canonical cell, which are only the ones flipping axes at 1 to -1.
+fn mirror_images(coord: &Coord) -> Vec<Coord> {
+    (FIRST_MIRRORED_AXIS..MAX_DIMENSIONS)
+        .filter(|axis| coord[*axis] == 1)
+        .fold(vec![*coord], |images, axis| {
+            images
+                .into_iter()
+                .flat_map(|image| {
+                    let mut mirrored = image;
+                    mirrored[axis] = -1;
+                    [image, mirrored]
+                })
+                .collect()
+        })
+}
+
+/// Computes the next generation by adding up the neighbor counts of all cells
+/// around live cells in a single pass over the live cells.
+///
+/// With `symmetric` set, `map` only holds the canonical cells (see `is_canonical`),
+/// and the mirror images of every live cell add to the counts of canonical cells.
+fn iterate_once(
+    map: &HashSet<Coord>,
+    offsets: &[Coord],
+    rules: &Rules,
+    symmetric: bool,
+) -> HashSet<Coord> {
+    let mut neighbor_counts = HashMap::<Coord, usize>::new();
+    for coord in map {
+        let images = if symmetric {
+            mirror_images(coord)
+        } else {
+            vec![*coord]
+        };
+        for image in images {
+            for offset in offsets {
+                let neighbor = add(&image, offset);
+                if !symmetric || is_canonical(&neighbor) {
+                    *neighbor_counts.entry(neighbor).or_default() += 1;
+                }
+            }
+        }
+    }
+
+    let lonely_cells = map
         .iter()
+        .filter(|coord| !neighbor_counts.contains_key(*coord) && rules.survival.contains(&0))
         .copied()
-        .flat_map(|coord| neighbor_indexes(coord, dim_4))
-        .filter(|neighbor_index| !map.contains(neighbor_index))
-        .collect::<HashSet<_>>()
+        .collect::<Vec<_>>();
+    neighbor_counts
         .into_iter()
-        .filter(|coord| alive_neighbors(map, *coord, dim_4) == 3);
-    remaining_cells.chain(born_cells).collect()
+        .filter(|(coord, count)| {
+            if map.contains(coord) {
+                rules.survival.contains(count)
+            } else {
+                rules.birth.contains(count)
+            }
+        })
+        .map(|(coord, _)| coord)
+        .chain(lonely_cells)
+        .collect()
+}
+
+/// Counts the live cells, weighting canonical cells by their mirror images in symmetric mode.
+fn population(map: &HashSet<Coord>, symmetric: bool) -> usize {
+    if symmetric {
+        map.iter().map(weight).sum()
+    } else {
+        map.len()
+    }
+}
+
+/// Runs the simulation for `cycles` cycles in `dimensions` dimensions (2 to 8),
+/// optionally storing only the canonical cells of the mirror symmetric axes.
+/// Every generation is passed to `on_generation` together with its cycle, starting with
+/// the initial one, and only the last one is returned.
+fn simulate(
+    cell_map: &HashSet<Coord>,
+    dimensions: usize,
+    cycles: usize,
+    rules: &Rules,
+    symmetric: bool,
+    mut on_generation: impl FnMut(usize, &HashSet<Coord>) -> Result<(), Box<dyn Error>>,
+) -> Result<HashSet<Coord>, Box<dyn Error>> {
+    if !(2..=MAX_DIMENSIONS).contains(&dimensions) {
+        return Err(format!("dimensions must be between 2 and {MAX_DIMENSIONS}").into());
+    }
+    let offsets = neighbor_offsets(dimensions);
+    let mut generation = cell_map.clone();
+    on_generation(0, &generation)?;
+    for cycle in 1..=cycles {
+        generation = iterate_once(&generation, &offsets, rules, symmetric);
+        on_generation(cycle, &generation)?;
+    }
+    Ok(generation)
+}
+
+/// Restores all mirror images of the canonical cells stored by the symmetric mode.
+fn expand(map: &HashSet<Coord>) -> HashSet<Coord> {
+    map.iter()
+        .flat_map(|coord| {
+            (FIRST_MIRRORED_AXIS..MAX_DIMENSIONS)
+                .filter(|axis| coord[*axis] != 0)
+                .fold(vec![*coord], |images, axis| {
+                    images
+                        .into_iter()
+                        .flat_map(|image| {
+                            let mut mirrored = image;
+                            mirrored[axis] = -image[axis];
+                            [image, mirrored]
+                        })
+                        .collect()
+                })
+        })
+        .collect()
+}
+
+const AXIS_NAMES: [&str; MAX_DIMENSIONS] = ["x", "y", "z", "w", "v", "u", "t", "s"];
+
+/// Prints a generation as the 2D x/y slices the puzzle uses, one per combination of the
+/// other axes (`z=-1, w=0`). All slices share the bounding box of the live cells and
+/// only slices that contain live cells are printed.
+fn format_slices(map: &HashSet<Coord>, dimensions: usize) -> String {
+    let (min, max) = map.iter().fold(
+        ([i32::MAX; MAX_DIMENSIONS], [i32::MIN; MAX_DIMENSIONS]),
+        |(mut min, mut max), coord| {
+            for axis in 0..MAX_DIMENSIONS {
+                min[axis] = min[axis].min(coord[axis]);
+                max[axis] = max[axis].max(coord[axis]);
+            }
+            (min, max)
+        },
+    );
+    if map.is_empty() {
+        return "(empty)\n".to_string();
+    }
+
+    let mut slices = BTreeMap::<Coord, HashSet<(i32, i32)>>::new();
+    for coord in map {
+        let mut slice = *coord;
+        (slice[0], slice[1]) = (0, 0);
+        slices
+            .entry(slice)
+            .or_default()
+            .insert((coord[0], coord[1]));
+    }
+
+    let mut output = String::new();
+    for (slice, cells) in slices {
+        let cell = |x, y| cells.contains(&(x, y));
+        let label = (2..dimensions)
+            .map(|axis| format!("{}={}", AXIS_NAMES[axis], slice[axis]))
+            .collect::<Vec<_>>()
+            .join(", ");
+        if !label.is_empty() {
+            output += &label;
+            output += "\n";
+        }
+        for y in min[1]..=max[1] {
+            output.extend((min[0]..=max[0]).map(|x| if cell(x, y) { '#' } else { '.' }));
+            output += "\n";
+        }
+        output += "\n";
+    }
+    output
 }
 
 fn main() -> Result<(), Box<dyn Error>> {
@@ -53,7 +279,9 @@ fn main() -> Result<(), Box<dyn Error>> {
                 .enumerate()
                 .filter_map(move |(char_index, char)| {
                     if char == '#' {
-                        Some((char_index as i32, line_index as i32, 0, 0))
+                        let mut coord = [0; MAX_DIMENSIONS];
+                        (coord[0], coord[1]) = (char_index as i32, line_index as i32);
+                        Some(coord)
                     } else {
                         None
                     }
@@ -61,12 +289,78 @@ fn main() -> Result<(), Box<dyn Error>> {
         })
         .collect::<HashSet<_>>();
 
-    let dim_3 = (0..6).fold(cell_map.clone(), |cell_map, _| {
-        iterate_once(&cell_map, false)
-    });
+    let (mut dimensions, mut cycles, mut rules, mut symmetric) = (3, 6, Rules::default(), false);
+    let (mut printed_generations, mut slices_directory) = (Vec::new(), None);
+    let mut arguments = args().skip(1).peekable();
+    let custom = arguments.peek().is_some();
+    while let Some(option) = arguments.next() {
+        let mut value = || {
+            arguments
+                .next()
+                .ok_or(format!("missing value for {option}"))
+        };
+        match option.as_str() {
+            "--dimensions" => dimensions = value()?.parse()?,
+            "--cycles" => cycles = value()?.parse()?,
+            "--rules" => rules = Rules::parse(&value()?)?,
+            "--symmetric" => symmetric = true,
+            "--print" => printed_generations.push(value()?.parse::<usize>()?),
+            "--slices" => slices_directory = Some(value()?),
+            _ => return Err(format!("unknown option {option}").into()),
+        }
+    }
+
+    if custom {
+        if let Some(&index) = printed_generations.iter().find(|&&index| index > cycles) {
+            return Err(format!("no generation {index}").into());
+        }
+        if let Some(directory) = &slices_directory {
+            create_dir_all(directory)?;
+        }
+
+        let last_generation = simulate(
+            &cell_map,
+            dimensions,
+            cycles,
+            &rules,
+            symmetric,
+            |cycle, generation| {
+                let printed = printed_generations.contains(&cycle);
+                if !printed && slices_directory.is_none() {
+                    return Ok(());
+                }
+                let slices = format_slices(
+                    &if symmetric {
+                        expand(generation)
+                    } else {
+                        generation.clone()
+                    },
+                    dimensions,
+                );
+                if printed {
+                    print!("{slices}");
+                }
+                if let Some(directory) = &slices_directory {
+                    write(
+                        Path::new(directory).join(format!("generation_{cycle}.txt")),
+                        slices,
+                    )?;
+                }
+                Ok(())
+            },
+        )?;
+
+        println!(
+            "2020-12-17 {dimensions}D: {}",
+            population(&last_generation, symmetric)
+        );
+        return Ok(());
+    }
+
+    let dim_3 = simulate(&cell_map, 3, 6, &Rules::default(), false, |_, _| Ok(()))?;
     println!("2020-12-17 Part 1: {}", dim_3.len());
 
-    let dim_4 = (0..6).fold(cell_map, |cell_map, _| iterate_once(&cell_map, true));
+    let dim_4 = simulate(&cell_map, 4, 6, &Rules::default(), false, |_, _| Ok(()))?;
     println!("2020-12-17 Part 2: {}", dim_4.len());
     Ok(())
 }
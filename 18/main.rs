@@ -1,129 +1,300 @@
-use std::{collections::VecDeque, error::Error, fs::read_to_string, iter::once};
+use std::{
+    collections::HashMap,
+    env::args,
+    error::Error,
+    fmt::{self, Display, Formatter},
+    fs::read_to_string,
+    iter::Peekable,
+};
 
-#[derive(Clone)]
-enum Term {
-    NumberChars(Vec<char>),
+#[derive(Clone, Copy)]
+enum Token {
     Number(i64),
-    Mark,
-    Plus(i64),
-    Times(i64),
+    Operator(char),
+    LeftParen,
+    RightParen,
 }
 
-fn evaluate_formula(formula: &str, hi_pred_plus: bool) -> i64 {
-    let mut formula_chars = formula
-        .replace(' ', "")
-        .chars()
-        .chain(once(' '))
-        .collect::<VecDeque<_>>();
-    let mut queue = VecDeque::new();
-
-    while let Some(formula_char) = formula_chars.pop_front() {
-        if formula_char.is_ascii_digit() {
-            match queue.get_mut(0) {
-                Some(Term::NumberChars(number_chars)) => {
-                    number_chars.push(formula_char);
-                    continue;
-                }
-                _ => {
-                    queue.push_front(Term::NumberChars(vec![formula_char]));
-                    continue;
+impl Display for Token {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        match self {
+            Self::Number(number) => write!(f, "{number}"),
+            Self::Operator(operator) => write!(f, "'{operator}'"),
+            Self::LeftParen => write!(f, "'('"),
+            Self::RightParen => write!(f, "')'"),
+        }
+    }
+}
+
+/// Splits a formula into tokens, each together with its position in the formula.
+fn tokenize(formula: &str) -> Result<Vec<(usize, Token)>, Box<dyn Error>> {
+    let mut tokens = Vec::new();
+    let mut chars = formula.char_indices().peekable();
+    while let Some((position, formula_char)) = chars.next() {
+        match formula_char {
+            ' ' => (),
+            '(' => tokens.push((position, Token::LeftParen)),
+            ')' => tokens.push((position, Token::RightParen)),
+            '+' | '-' | '*' | '/' | '^' => tokens.push((position, Token::Operator(formula_char))),
+            '0'..='9' => {
+                let mut end = position + 1;
+                while let Some((next_position, '0'..='9')) = chars.peek() {
+                    end = next_position + 1;
+                    chars.next();
                 }
+                tokens.push((position, Token::Number(formula[position..end].parse()?)));
             }
-        } else if let Some(Term::NumberChars(number_chars)) = queue.get(0) {
-            let number = number_chars
-                .iter()
-                .collect::<String>()
-                .parse::<i64>()
-                .unwrap();
-            queue.pop_front();
-            if let Some(Term::Plus(summand)) = queue.get(0).cloned() {
-                queue.pop_front();
-                if let (true, Some(Term::Times(factor)), true) =
-                    (hi_pred_plus, queue.get(0).cloned(), formula_char != '+')
-                {
-                    queue.pop_front();
-                    queue.push_front(Term::Number(factor * (summand + number)));
-                } else {
-                    queue.push_front(Term::Number(summand + number));
-                }
-            } else if let Some(Term::Times(factor)) = queue.get(0).cloned() {
-                if hi_pred_plus && formula_char == '+' {
-                    queue.push_front(Term::Number(number));
-                } else {
-                    queue.pop_front();
-                    queue.push_front(Term::Number(factor * number));
-                }
-            } else {
-                queue.push_front(Term::Number(number));
+            _ => {
+                return Err(
+                    format!("unexpected '{formula_char}' at {position} in {formula}").into(),
+                )
             }
         }
+    }
+    Ok(tokens)
+}
 
-        match formula_char {
-            '(' => queue.push_front(Term::Mark),
-            ')' => {
-                if let (Some(Term::Number(number)), Some(Term::Mark)) =
-                    (queue.pop_front(), queue.pop_front())
-                {
-                    if let Some(Term::Plus(summand)) = queue.get(0).cloned() {
-                        queue.pop_front();
-                        if let (true, Some(Term::Times(factor)), true) = (
-                            hi_pred_plus,
-                            queue.get(0).cloned(),
-                            formula_chars.get(0) != Some(&'+'),
-                        ) {
-                            queue.pop_front();
-                            queue.push_front(Term::Number(factor * (summand + number)));
-                        } else {
-                            queue.push_front(Term::Number(summand + number));
-                        }
-                    } else if let Some(Term::Times(factor)) = queue.get(0).cloned() {
-                        if hi_pred_plus && formula_chars.get(0) == Some(&'+') {
-                            queue.push_front(Term::Number(number));
-                        } else {
-                            queue.pop_front();
-                            queue.push_front(Term::Number(factor * number));
-                        }
-                    } else {
-                        queue.push_front(Term::Number(number));
-                    }
+enum Expression {
+    Number(i64),
+    Unary(char, Box<Expression>),
+    Binary(char, Box<Expression>, Box<Expression>),
+}
+
+impl Expression {
+    fn evaluate(&self) -> Result<i64, Box<dyn Error>> {
+        match self {
+            Self::Number(number) => Ok(*number),
+            Self::Unary(operator, operand) => {
+                let operand = operand.evaluate()?;
+                match operator {
+                    '-' => operand.checked_neg(),
+                    _ => None,
                 }
+                .ok_or_else(|| format!("cannot evaluate {operator}{operand}").into())
             }
-            '+' => {
-                if let Some(Term::Number(number)) = queue.pop_front() {
-                    queue.push_front(Term::Plus(number));
+            Self::Binary(operator, left, right) => {
+                let (left, right) = (left.evaluate()?, right.evaluate()?);
+                match operator {
+                    '+' => left.checked_add(right),
+                    '-' => left.checked_sub(right),
+                    '*' => left.checked_mul(right),
+                    '/' => left.checked_div(right),
+                    '^' => u32::try_from(right)
+                        .ok()
+                        .and_then(|exponent| left.checked_pow(exponent)),
+                    _ => None,
                 }
+                .ok_or_else(|| format!("cannot evaluate {left} {operator} {right}").into())
             }
-            '*' => {
-                if let Some(Term::Number(number)) = queue.pop_front() {
-                    queue.push_front(Term::Times(number));
+        }
+    }
+}
+
+/// Writes the expression fully parenthesized, which shows how it was parsed.
+impl Display for Expression {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        match self {
+            Self::Number(number) => write!(f, "{number}"),
+            Self::Unary(operator, operand) => write!(f, "({operator}{operand})"),
+            Self::Binary(operator, left, right) => write!(f, "({left} {operator} {right})"),
+        }
+    }
+}
+
+#[derive(Clone, Copy)]
+enum Associativity {
+    Left,
+    Right,
+}
+
+/// The binding power and associativity of every operator the parser accepts.
+/// Higher precedence binds tighter. Prefix operators apply to everything to their
+/// right that binds at least as tight as they do, so `-2 ^ 2` is `-(2 ^ 2)` when
+/// prefix `-` and `^` share a precedence.
+struct OperatorTable {
+    operators: HashMap<char, (u8, Associativity)>,
+    prefix_operators: HashMap<char, u8>,
+}
+
+impl OperatorTable {
+    fn new(operators: &[(char, u8, Associativity)], prefix_operators: &[(char, u8)]) -> Self {
+        Self {
+            operators: operators
+                .iter()
+                .map(|&(operator, precedence, associativity)| {
+                    (operator, (precedence, associativity))
+                })
+                .collect(),
+            prefix_operators: prefix_operators.iter().copied().collect(),
+        }
+    }
+
+    /// All operators bind equally and are evaluated left to right.
+    fn part_1() -> Self {
+        use Associativity::Left;
+        Self::new(
+            &[
+                ('+', 1, Left),
+                ('-', 1, Left),
+                ('*', 1, Left),
+                ('/', 1, Left),
+            ],
+            &[],
+        )
+    }
+
+    /// Addition binds tighter than multiplication.
+    fn part_2() -> Self {
+        use Associativity::Left;
+        Self::new(
+            &[
+                ('+', 2, Left),
+                ('-', 2, Left),
+                ('*', 1, Left),
+                ('/', 1, Left),
+            ],
+            &[],
+        )
+    }
+
+    /// The usual rules, including right associative exponentiation and negation.
+    fn standard() -> Self {
+        use Associativity::{Left, Right};
+        Self::new(
+            &[
+                ('+', 1, Left),
+                ('-', 1, Left),
+                ('*', 2, Left),
+                ('/', 2, Left),
+                ('^', 3, Right),
+            ],
+            &[('-', 3)],
+        )
+    }
+
+    fn preset(name: &str) -> Result<Self, Box<dyn Error>> {
+        match name {
+            "part1" => Ok(Self::part_1()),
+            "part2" => Ok(Self::part_2()),
+            "standard" => Ok(Self::standard()),
+            _ => Err(format!("unknown preset {name}").into()),
+        }
+    }
+
+    /// Parses the tokens into an expression tree by precedence climbing.
+    fn parse(&self, tokens: &[(usize, Token)]) -> Result<Expression, Box<dyn Error>> {
+        let mut tokens = tokens.iter().copied().peekable();
+        let expression = self.parse_expression(&mut tokens, 0)?;
+        match tokens.next() {
+            None => Ok(expression),
+            Some((position, token)) => Err(format!("unexpected {token} at {position}").into()),
+        }
+    }
+
+    fn parse_expression(
+        &self,
+        tokens: &mut Peekable<impl Iterator<Item = (usize, Token)>>,
+        min_precedence: u8,
+    ) -> Result<Expression, Box<dyn Error>> {
+        let mut left = match tokens.next() {
+            Some((_, Token::Number(number))) => Expression::Number(number),
+            Some((position, Token::LeftParen)) => {
+                let inner = self.parse_expression(tokens, 0)?;
+                match tokens.next() {
+                    Some((_, Token::RightParen)) => inner,
+                    Some((closing_position, token)) => return Err(format!(
+                        "expected ')' for '(' at {position}, found {token} at {closing_position}"
+                    )
+                    .into()),
+                    None => return Err(format!("missing ')' for '(' at {position}").into()),
                 }
             }
-            ' ' => (),
-            _ => unreachable!(),
+            Some((position, Token::Operator(operator))) => {
+                let &precedence = self.prefix_operators.get(&operator).ok_or_else(|| {
+                    format!("operator '{operator}' at {position} is not a prefix operator")
+                })?;
+                let operand = self.parse_expression(tokens, precedence)?;
+                Expression::Unary(operator, Box::new(operand))
+            }
+            Some((position, token)) => {
+                return Err(format!("unexpected {token} at {position}").into())
+            }
+            None => return Err("unexpected end of formula".into()),
+        };
+
+        while let Some(&(position, Token::Operator(operator))) = tokens.peek() {
+            let &(precedence, associativity) = self.operators.get(&operator).ok_or_else(|| {
+                format!("operator '{operator}' at {position} is not in the table")
+            })?;
+            if precedence < min_precedence {
+                break;
+            }
+            tokens.next();
+            let next_min_precedence = match associativity {
+                Associativity::Left => precedence + 1,
+                Associativity::Right => precedence,
+            };
+            let right = self.parse_expression(tokens, next_min_precedence)?;
+            left = Expression::Binary(operator, Box::new(left), Box::new(right));
         }
+        Ok(left)
     }
+}
 
-    match queue.pop_front() {
-        Some(Term::Number(number)) => number,
-        _ => unreachable!(),
-    }
+fn evaluate_formula(formula: &str, table: &OperatorTable) -> Result<i64, Box<dyn Error>> {
+    table.parse(&tokenize(formula)?)?.evaluate()
 }
 
 fn main() -> Result<(), Box<dyn Error>> {
     let formulas = read_to_string("input.txt")?
         .split("\n")
+        .filter(|formula| !formula.is_empty())
         .map(ToString::to_string)
         .collect::<Vec<_>>();
 
+    let (mut preset, mut expressions) = (None, Vec::new());
+    let mut arguments = args().skip(1);
+    while let Some(option) = arguments.next() {
+        let value = arguments
+            .next()
+            .ok_or(format!("missing value for {option}"))?;
+        match option.as_str() {
+            "--preset" => preset = Some(value),
+            "--eval" => expressions.push(value),
+            _ => return Err(format!("unknown option {option}").into()),
+        }
+    }
+
+    if preset.is_some() || !expressions.is_empty() {
+        let table = match &preset {
+            Some(name) => OperatorTable::preset(name)?,
+            None => OperatorTable::standard(),
+        };
+        for formula in expressions {
+            let expression = table.parse(&tokenize(&formula)?)?;
+            println!("{expression} = {}", expression.evaluate()?);
+        }
+        if let Some(name) = preset {
+            let sum = formulas
+                .iter()
+                .map(|formula| evaluate_formula(formula, &table))
+                .sum::<Result<i64, _>>()?;
+            println!("2020-12-18 Preset {name}: {sum}");
+        }
+        return Ok(());
+    }
+
     let part1 = formulas
         .iter()
-        .map(|formula| evaluate_formula(formula, false))
-        .sum::<i64>();
+        .map(|formula| evaluate_formula(formula, &OperatorTable::part_1()))
+        .sum::<Result<i64, _>>()?;
     println!("2020-12-18 Part 1: {part1}");
 
     let part2 = formulas
         .iter()
-        .map(|formula| evaluate_formula(formula, true))
-        .sum::<i64>();
-    Ok(println!("2020-12-18 Part 2: {part2}"))
+        .map(|formula| evaluate_formula(formula, &OperatorTable::part_2()))
+        .sum::<Result<i64, _>>()?;
+    println!("2020-12-18 Part 2: {part2}");
+
+    Ok(())
 }